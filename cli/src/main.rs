@@ -1,33 +1,104 @@
+use core::{BessyError, Interpreter, Options};
 use std::io::Write;
+use std::process::ExitCode;
 
-fn get_input(prompt: &str) -> String {
+// returns None once the input is closed
+fn get_input(prompt: &str) -> Option<String> {
     let mut input = String::new();
     print!("{prompt} ");
     let _ = std::io::stdout().flush();
-    let _ = std::io::stdin().read_line(&mut input).unwrap();
+    match std::io::stdin().read_line(&mut input) {
+        Ok(0) | Err(_) => return None,
+        Ok(_) => {}
+    }
     let _ = input.pop();
 
-    input
+    Some(input)
+}
+
+// one interpreter is kept for the whole session so that the globals
+// defined on one line can be used on the next
+fn repl(options: Options) {
+    let mut interpreter = Interpreter::with_options(options);
+    while let Some(input) = get_input("bessy>>") {
+        if let Err(errors) =
+            interpreter.evaluate(&input, &mut std::io::stdout())
+        {
+            eprint!("{}", errors.render(&input));
+        }
+    }
+}
+
+fn read_file(path: &str) -> Result<String, ExitCode> {
+    std::fs::read_to_string(path).map_err(|err| {
+        eprintln!("Could not read file \"{path}\": {err}.");
+        ExitCode::from(74)
+    })
 }
 
-fn repl() {
-    loop {
-        let input = get_input("bessy>>");
-        match core::evaluate(&input) {
-            Ok(()) => continue,
-            Err(msg) => eprintln!("{msg}"),
+// exit codes follow the conventions of sysexits.h
+fn run_file(path: &str, options: Options) -> ExitCode {
+    let input = match read_file(path) {
+        Ok(input) => input,
+        Err(code) => return code,
+    };
+    let result = core::evaluate_with_options(
+        &input,
+        &mut std::io::stdout(),
+        &mut std::io::stderr(),
+        options,
+    );
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(errors) => {
+            if matches!(*errors, [BessyError::Runtime { .. }]) {
+                ExitCode::from(70)
+            } else {
+                ExitCode::from(65)
+            }
         }
     }
 }
 
-fn evaluate_file() {
-    let input = include_str!("../../test/hello.lox");
-    match core::evaluate(input) {
-        Ok(()) => {},
-        Err(msg) => eprintln!("{msg}"),
+// prints the bytecode the script compiles to without running it
+fn dump_bytecode(path: &str) -> ExitCode {
+    let input = match read_file(path) {
+        Ok(input) => input,
+        Err(code) => return code,
+    };
+    match core::disassemble(&input) {
+        Ok(listing) => {
+            print!("{listing}");
+            ExitCode::SUCCESS
+        }
+        Err(errors) => {
+            eprint!("{}", errors.render(&input));
+            ExitCode::from(65)
+        }
     }
 }
 
-fn main() {
-    repl()
+fn main() -> ExitCode {
+    let mut options = Options::default();
+    let mut dump = false;
+    let mut paths = Vec::new();
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "--trace" => options.trace = true,
+            "--dump-bytecode" => dump = true,
+            _ => paths.push(arg),
+        }
+    }
+    match (paths.as_slice(), dump) {
+        ([], false) => {
+            repl(options);
+            ExitCode::SUCCESS
+        }
+        ([path], false) => run_file(path, options),
+        ([path], true) => dump_bytecode(path),
+        _ => {
+            eprintln!("Usage: bessy [--trace | --dump-bytecode] [path]");
+            ExitCode::from(64)
+        }
+    }
 }
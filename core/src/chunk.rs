@@ -0,0 +1,65 @@
+use crate::lexer::Span;
+use crate::value::Value;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OpCode {
+    Constant(u8),
+    Nil,
+    True,
+    False,
+    Pop,
+    GetLocal(u8),
+    SetLocal(u8),
+    GetUpvalue(u8),
+    SetUpvalue(u8),
+    GetGlobal(u8),
+    DefineGlobal(u8),
+    SetGlobal(u8),
+    Equal,
+    Greater,
+    Less,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Modulo,
+    Not,
+    Negate,
+    Stringify,
+    Print,
+    Jump(u16),
+    JumpIfFalse(u16),
+    Loop(u16),
+    Call(u8),
+    Closure(u8),
+    CloseUpvalue,
+    Class(u8),
+    Method(u8),
+    GetProperty(u8),
+    SetProperty(u8),
+    Inherit,
+    GetSuper(u8),
+    Return,
+}
+
+// a chunk is a sequence of instructions along with the constants
+// they refer to and the source location each of them came from
+#[derive(Clone, Debug, Default)]
+pub struct Chunk {
+    pub code: Vec<OpCode>,
+    pub spans: Vec<Span>,
+    pub constants: Vec<Value>,
+}
+
+impl Chunk {
+    pub fn write(&mut self, opcode: OpCode, span: Span) -> usize {
+        self.code.push(opcode);
+        self.spans.push(span);
+        self.code.len() - 1
+    }
+
+    pub fn add_constant(&mut self, value: Value) -> usize {
+        self.constants.push(value);
+        self.constants.len() - 1
+    }
+}
@@ -0,0 +1,743 @@
+// a compiler walks the abstract syntax tree and lowers it into a
+// chunk of bytecode that can be executed by the virtual machine
+use crate::chunk::{Chunk, OpCode};
+use crate::error::BessyError;
+use crate::expr::Expr;
+use crate::heap::{Heap, ObjRef};
+use crate::lexer::{Position, Span, Token, TokenType};
+use crate::object::{Obj, ObjFunction, Upvalue};
+use crate::resolver;
+use crate::stmt::Stmt;
+use crate::value::Value;
+use std::collections::HashMap;
+
+const MAX_LOCALS: usize = u8::MAX as usize + 1;
+const FIRST_LINE: Position = Position {
+    offset: 0,
+    line_number: 1,
+    column_number: 0,
+};
+
+struct Local {
+    name: String,
+    depth: usize,
+    // set when a closure refers to this local, so that it gets moved
+    // off the stack when it goes out of scope
+    is_captured: bool,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum FunctionKind {
+    Script,
+    Function,
+    Method,
+    Initializer,
+}
+
+// compilation state of the function whose body is being lowered,
+// nested function declarations push a new one on top
+struct FunctionState {
+    function: ObjFunction,
+    kind: FunctionKind,
+    locals: Vec<Local>,
+    scope_depth: usize,
+    // values already in the constant table, strings are interned so
+    // every use of a name or a string literal shares one constant
+    constants: HashMap<ConstantKey, u8>,
+}
+
+// numbers are compared by their bits so that they can be hashed
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum ConstantKey {
+    Number(u64),
+    Obj(ObjRef),
+}
+
+impl FunctionState {
+    fn new(kind: FunctionKind, name: Option<ObjRef>) -> Self {
+        // slot zero is reserved for the function being called, or
+        // for the receiver when the function is a method
+        let reserved = Local {
+            name: match kind {
+                FunctionKind::Method | FunctionKind::Initializer => {
+                    String::from("this")
+                }
+                FunctionKind::Script | FunctionKind::Function => String::new(),
+            },
+            depth: 0,
+            is_captured: false,
+        };
+        Self {
+            function: ObjFunction {
+                name,
+                ..Default::default()
+            },
+            kind,
+            locals: vec![reserved],
+            scope_depth: 0,
+            constants: HashMap::new(),
+        }
+    }
+}
+
+pub struct Compiler<'heap> {
+    states: Vec<FunctionState>,
+    // variables the resolver found a local declaration for, any
+    // other variable is global
+    locals: HashMap<Span, usize>,
+    span: Span,
+    heap: &'heap mut Heap,
+    // values the virtual machine keeps alive while the compiler runs
+    roots: Vec<Value>,
+}
+
+pub fn identifier(token: &Token) -> &str {
+    match &token.kind {
+        TokenType::Identifier(name) => name,
+        _ => unreachable!(),
+    }
+}
+
+impl<'heap> Compiler<'heap> {
+    pub fn new(heap: &'heap mut Heap, roots: Vec<Value>) -> Self {
+        Self {
+            states: vec![FunctionState::new(FunctionKind::Script, None)],
+            locals: HashMap::new(),
+            // the return at the end of an empty script has no token to
+            // take its line from, so it is put on the first line
+            span: Span {
+                start: FIRST_LINE,
+                end: FIRST_LINE,
+            },
+            heap,
+            roots,
+        }
+    }
+
+    pub fn compile(mut self, stmts: &[Stmt]) -> Result<ObjRef, BessyError> {
+        self.locals = resolver::resolve(stmts)?;
+        for stmt in stmts {
+            self.statement(stmt)?;
+        }
+        let script = self.end_function();
+        Ok(self.alloc(Obj::Function(script)))
+    }
+
+    // the functions being compiled are not on the heap yet, so the
+    // objects in their constant tables have to be marked separately
+    fn collect_garbage(&mut self) {
+        for &root in &self.roots {
+            self.heap.mark_value(root);
+        }
+        for state in &self.states {
+            if let Some(name) = state.function.name {
+                self.heap.mark_object(name);
+            }
+            for &constant in &state.function.chunk.constants {
+                self.heap.mark_value(constant);
+            }
+        }
+        self.heap.collect();
+    }
+
+    fn alloc(&mut self, obj: Obj) -> ObjRef {
+        if self.heap.should_collect() {
+            self.heap.mark_references(&obj);
+            self.collect_garbage();
+        }
+        self.heap.alloc(obj)
+    }
+
+    fn intern(&mut self, string: &str) -> ObjRef {
+        if self.heap.should_collect() {
+            self.collect_garbage();
+        }
+        self.heap.intern(string)
+    }
+
+    fn state(&mut self) -> &mut FunctionState {
+        self.states.last_mut().unwrap()
+    }
+
+    fn chunk(&mut self) -> &mut Chunk {
+        &mut self.state().function.chunk
+    }
+
+    fn error(&self, message: &str) -> BessyError {
+        BessyError::Compile {
+            msg: message.into(),
+            span: self.span,
+        }
+    }
+
+    fn emit(&mut self, opcode: OpCode) -> usize {
+        let span = self.span;
+        self.chunk().write(opcode, span)
+    }
+
+    fn make_constant(&mut self, value: Value) -> Result<u8, BessyError> {
+        let key = match value {
+            Value::Number(num) => Some(ConstantKey::Number(num.to_bits())),
+            Value::Obj(obj) => Some(ConstantKey::Obj(obj)),
+            Value::Nil | Value::Boolean(_) => None,
+        };
+        if let Some(&index) =
+            key.and_then(|key| self.state().constants.get(&key))
+        {
+            return Ok(index);
+        }
+        let index = self.chunk().add_constant(value);
+        let index = u8::try_from(index)
+            .map_err(|_| self.error("Too many constants in one chunk."))?;
+        if let Some(key) = key {
+            self.state().constants.insert(key, index);
+        }
+        Ok(index)
+    }
+
+    fn emit_constant(&mut self, value: Value) -> Result<(), BessyError> {
+        let index = self.make_constant(value)?;
+        self.emit(OpCode::Constant(index));
+        Ok(())
+    }
+
+    fn identifier_constant(&mut self, name: &str) -> Result<u8, BessyError> {
+        let name = self.intern(name);
+        self.make_constant(Value::Obj(name))
+    }
+
+    fn patch_jump(&mut self, jump: usize) -> Result<(), BessyError> {
+        let offset = self.chunk().code.len() - jump - 1;
+        let offset = u16::try_from(offset)
+            .map_err(|_| self.error("Too much code to jump over."))?;
+        match &mut self.chunk().code[jump] {
+            OpCode::Jump(target) | OpCode::JumpIfFalse(target) => {
+                *target = offset
+            }
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+
+    fn emit_loop(&mut self, loop_start: usize) -> Result<(), BessyError> {
+        let offset = self.chunk().code.len() + 1 - loop_start;
+        let offset = u16::try_from(offset)
+            .map_err(|_| self.error("Loop body too large."))?;
+        self.emit(OpCode::Loop(offset));
+        Ok(())
+    }
+
+    // an initializer always returns the instance it was called on
+    fn emit_return(&mut self) {
+        if self.state().kind == FunctionKind::Initializer {
+            self.emit(OpCode::GetLocal(0));
+        } else {
+            self.emit(OpCode::Nil);
+        }
+        self.emit(OpCode::Return);
+    }
+
+    fn end_function(&mut self) -> ObjFunction {
+        self.emit_return();
+        self.states.pop().unwrap().function
+    }
+
+    fn begin_scope(&mut self) {
+        self.state().scope_depth += 1;
+    }
+
+    fn end_scope(&mut self) {
+        let state = self.state();
+        state.scope_depth -= 1;
+        let depth = state.scope_depth;
+        while self
+            .state()
+            .locals
+            .last()
+            .is_some_and(|local| local.depth > depth)
+        {
+            let local = self.state().locals.pop().unwrap();
+            if local.is_captured {
+                self.emit(OpCode::CloseUpvalue);
+            } else {
+                self.emit(OpCode::Pop);
+            }
+        }
+    }
+
+    fn add_local(&mut self, name: &Token) -> Result<(), BessyError> {
+        if self.state().locals.len() == MAX_LOCALS {
+            return Err(self.error("Too many local variables in function."));
+        }
+        let state = self.state();
+        let local = Local {
+            name: identifier(name).to_string(),
+            depth: state.scope_depth,
+            is_captured: false,
+        };
+        state.locals.push(local);
+        Ok(())
+    }
+
+    fn resolve_local(&self, state: usize, name: &str) -> Option<u8> {
+        self.states[state]
+            .locals
+            .iter()
+            .rposition(|local| local.name == name)
+            .map(|index| index as u8)
+    }
+
+    fn add_upvalue(
+        &mut self,
+        state: usize,
+        upvalue: Upvalue,
+    ) -> Result<u8, BessyError> {
+        let upvalues = &mut self.states[state].function.upvalues;
+        if let Some(index) = upvalues.iter().position(|&u| u == upvalue) {
+            return Ok(index as u8);
+        }
+        if upvalues.len() == MAX_LOCALS {
+            return Err(self.error("Too many closure variables in function."));
+        }
+        upvalues.push(upvalue);
+        Ok((upvalues.len() - 1) as u8)
+    }
+
+    // a variable that is neither a local of the current function nor
+    // a global is looked up in the enclosing functions, every function
+    // in between captures it so that it can pass it down
+    fn resolve_upvalue(
+        &mut self,
+        state: usize,
+        name: &str,
+    ) -> Result<Option<u8>, BessyError> {
+        if state == 0 {
+            return Ok(None);
+        }
+        if let Some(index) = self.resolve_local(state - 1, name) {
+            self.states[state - 1].locals[index as usize].is_captured = true;
+            let upvalue = Upvalue {
+                index,
+                is_local: true,
+            };
+            return self.add_upvalue(state, upvalue).map(Some);
+        }
+        if let Some(index) = self.resolve_upvalue(state - 1, name)? {
+            let upvalue = Upvalue {
+                index,
+                is_local: false,
+            };
+            return self.add_upvalue(state, upvalue).map(Some);
+        }
+        Ok(None)
+    }
+
+    fn statement(&mut self, stmt: &Stmt) -> Result<(), BessyError> {
+        match stmt {
+            Stmt::Var { name, init } => self.var_declaration(name, init),
+            Stmt::Print { keyword, value } => {
+                self.expression(value)?;
+                self.span = keyword.span;
+                self.emit(OpCode::Print);
+                Ok(())
+            }
+            Stmt::Expression { expr, semicolon } => {
+                self.expression(expr)?;
+                self.span = semicolon.span;
+                self.emit(OpCode::Pop);
+                Ok(())
+            }
+            Stmt::Block(stmts) => {
+                self.begin_scope();
+                for stmt in stmts {
+                    self.statement(stmt)?;
+                }
+                self.end_scope();
+                Ok(())
+            }
+            Stmt::If {
+                condition,
+                then,
+                elze,
+            } => self.if_statement(condition, then, elze.as_deref()),
+            Stmt::While { condition, body } => {
+                self.while_statement(condition, body)
+            }
+            Stmt::Function { name, params, body } => {
+                self.fun_declaration(name, params, body)
+            }
+            Stmt::Class {
+                name,
+                superclass,
+                methods,
+            } => self.class_declaration(name, superclass.as_ref(), methods),
+            Stmt::Return { keyword, value } => {
+                self.span = keyword.span;
+                if let Some(value) = value {
+                    self.expression(value)?;
+                    self.emit(OpCode::Return);
+                } else {
+                    self.emit_return();
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn var_declaration(
+        &mut self,
+        name: &Token,
+        init: &Option<Expr>,
+    ) -> Result<(), BessyError> {
+        self.span = name.span;
+        if let Some(init) = init {
+            self.expression(init)?;
+        } else {
+            self.emit(OpCode::Nil);
+        }
+        self.define_variable(name)
+    }
+
+    // a local variable lives in the stack slot its initializer was
+    // evaluated into, so only globals need an explicit instruction
+    fn define_variable(&mut self, name: &Token) -> Result<(), BessyError> {
+        self.span = name.span;
+        if self.state().scope_depth > 0 {
+            self.add_local(name)
+        } else {
+            let index = self.identifier_constant(identifier(name))?;
+            self.emit(OpCode::DefineGlobal(index));
+            Ok(())
+        }
+    }
+
+    fn get_variable(&mut self, name: &str) -> Result<(), BessyError> {
+        let current = self.states.len() - 1;
+        if let Some(slot) = self.resolve_local(current, name) {
+            self.emit(OpCode::GetLocal(slot));
+        } else if let Some(index) = self.resolve_upvalue(current, name)? {
+            self.emit(OpCode::GetUpvalue(index));
+        } else {
+            self.get_global(name)?;
+        }
+        Ok(())
+    }
+
+    fn get_global(&mut self, name: &str) -> Result<(), BessyError> {
+        let index = self.identifier_constant(name)?;
+        self.emit(OpCode::GetGlobal(index));
+        Ok(())
+    }
+
+    fn set_variable(&mut self, name: &str) -> Result<(), BessyError> {
+        let current = self.states.len() - 1;
+        if let Some(slot) = self.resolve_local(current, name) {
+            self.emit(OpCode::SetLocal(slot));
+        } else if let Some(index) = self.resolve_upvalue(current, name)? {
+            self.emit(OpCode::SetUpvalue(index));
+        } else {
+            self.set_global(name)?;
+        }
+        Ok(())
+    }
+
+    fn set_global(&mut self, name: &str) -> Result<(), BessyError> {
+        let index = self.identifier_constant(name)?;
+        self.emit(OpCode::SetGlobal(index));
+        Ok(())
+    }
+
+    fn fun_declaration(
+        &mut self,
+        name: &Token,
+        params: &[Token],
+        body: &[Stmt],
+    ) -> Result<(), BessyError> {
+        self.span = name.span;
+        let is_local = self.state().scope_depth > 0;
+        if is_local {
+            // declare the function before compiling its body so that
+            // it can refer to itself recursively
+            self.add_local(name)?;
+        }
+        self.function(FunctionKind::Function, name, params, body)?;
+        if !is_local {
+            let index = self.identifier_constant(identifier(name))?;
+            self.emit(OpCode::DefineGlobal(index));
+        }
+        Ok(())
+    }
+
+    fn class_declaration(
+        &mut self,
+        name: &Token,
+        superclass: Option<&Expr>,
+        methods: &[Stmt],
+    ) -> Result<(), BessyError> {
+        self.span = name.span;
+        let index = self.identifier_constant(identifier(name))?;
+        self.emit(OpCode::Class(index));
+        self.define_variable(name)?;
+
+        if let Some(superclass) = superclass {
+            let Expr::Variable(superclass_name) = superclass else {
+                unreachable!()
+            };
+            self.span = superclass_name.span;
+            self.get_variable(identifier(superclass_name))?;
+            self.get_variable(identifier(name))?;
+            self.emit(OpCode::Inherit);
+        }
+
+        // keep the class on top of the stack while its methods are
+        // being bound to it
+        self.get_variable(identifier(name))?;
+        for method in methods {
+            let Stmt::Function { name, params, body } = method else {
+                unreachable!()
+            };
+            let kind = if identifier(name) == "init" {
+                FunctionKind::Initializer
+            } else {
+                FunctionKind::Method
+            };
+            self.function(kind, name, params, body)?;
+            let index = self.identifier_constant(identifier(name))?;
+            self.emit(OpCode::Method(index));
+        }
+        self.emit(OpCode::Pop);
+        Ok(())
+    }
+
+    fn function(
+        &mut self,
+        kind: FunctionKind,
+        name: &Token,
+        params: &[Token],
+        body: &[Stmt],
+    ) -> Result<(), BessyError> {
+        let span = name.span;
+        self.span = span;
+        let name = self.intern(identifier(name));
+        self.states.push(FunctionState::new(kind, Some(name)));
+        self.begin_scope();
+        for param in params {
+            self.span = param.span;
+            if self.state().function.arity == u8::MAX as usize {
+                return Err(self.error("Can't have more than 255 parameters."));
+            }
+            self.state().function.arity += 1;
+            self.add_local(param)?;
+        }
+        for stmt in body {
+            self.statement(stmt)?;
+        }
+        let function = self.end_function();
+        // the closure is created where the function is declared and
+        // not on the last line of its body
+        self.span = span;
+        let function = self.alloc(Obj::Function(function));
+        let index = self.make_constant(Value::Obj(function))?;
+        self.emit(OpCode::Closure(index));
+        Ok(())
+    }
+
+    fn if_statement(
+        &mut self,
+        condition: &Expr,
+        then: &Stmt,
+        elze: Option<&Stmt>,
+    ) -> Result<(), BessyError> {
+        self.expression(condition)?;
+        let then_jump = self.emit(OpCode::JumpIfFalse(0));
+        self.emit(OpCode::Pop);
+        self.statement(then)?;
+        let else_jump = self.emit(OpCode::Jump(0));
+        self.patch_jump(then_jump)?;
+        self.emit(OpCode::Pop);
+        if let Some(elze) = elze {
+            self.statement(elze)?;
+        }
+        self.patch_jump(else_jump)
+    }
+
+    fn while_statement(
+        &mut self,
+        condition: &Expr,
+        body: &Stmt,
+    ) -> Result<(), BessyError> {
+        let loop_start = self.chunk().code.len();
+        self.expression(condition)?;
+        let exit_jump = self.emit(OpCode::JumpIfFalse(0));
+        self.emit(OpCode::Pop);
+        self.statement(body)?;
+        self.emit_loop(loop_start)?;
+        self.patch_jump(exit_jump)?;
+        self.emit(OpCode::Pop);
+        Ok(())
+    }
+
+    fn expression(&mut self, expr: &Expr) -> Result<(), BessyError> {
+        match expr {
+            Expr::Number(num, span) => {
+                self.span = *span;
+                self.emit_constant(Value::Number(*num))
+            }
+            Expr::String(lexeme, span) => {
+                self.span = *span;
+                let string = self.intern(lexeme);
+                self.emit_constant(Value::Obj(string))
+            }
+            Expr::Boolean(value, span) => {
+                self.span = *span;
+                self.emit(if *value { OpCode::True } else { OpCode::False });
+                Ok(())
+            }
+            Expr::Nil(span) => {
+                self.span = *span;
+                self.emit(OpCode::Nil);
+                Ok(())
+            }
+            Expr::Group(expr) => self.expression(expr),
+            Expr::Stringify(expr) => {
+                self.expression(expr)?;
+                self.emit(OpCode::Stringify);
+                Ok(())
+            }
+            Expr::Variable(name) => {
+                self.span = name.span;
+                if self.locals.contains_key(&name.span) {
+                    self.get_variable(identifier(name))
+                } else {
+                    self.get_global(identifier(name))
+                }
+            }
+            Expr::Assign { name, value } => {
+                self.expression(value)?;
+                self.span = name.span;
+                if self.locals.contains_key(&name.span) {
+                    self.set_variable(identifier(name))
+                } else {
+                    self.set_global(identifier(name))
+                }
+            }
+            Expr::Get { object, name } => {
+                self.expression(object)?;
+                self.span = name.span;
+                let index = self.identifier_constant(identifier(name))?;
+                self.emit(OpCode::GetProperty(index));
+                Ok(())
+            }
+            Expr::Set {
+                object,
+                name,
+                value,
+            } => {
+                self.expression(object)?;
+                self.expression(value)?;
+                self.span = name.span;
+                let index = self.identifier_constant(identifier(name))?;
+                self.emit(OpCode::SetProperty(index));
+                Ok(())
+            }
+            Expr::This(keyword) => {
+                self.span = keyword.span;
+                self.get_variable("this")
+            }
+            Expr::Super { keyword, method } => {
+                self.span = keyword.span;
+                self.get_variable("this")?;
+                self.span = method.span;
+                let index = self.identifier_constant(identifier(method))?;
+                self.emit(OpCode::GetSuper(index));
+                Ok(())
+            }
+            Expr::Logical { left, oper, right } => {
+                self.expression(left)?;
+                self.span = oper.span;
+                match oper.kind {
+                    TokenType::And => self.and(right),
+                    TokenType::Or => self.or(right),
+                    _ => unreachable!(),
+                }
+            }
+            Expr::Unary { oper, right } => {
+                self.expression(right)?;
+                self.span = oper.span;
+                match oper.kind {
+                    TokenType::Minus => self.emit(OpCode::Negate),
+                    TokenType::Bang => self.emit(OpCode::Not),
+                    _ => unreachable!(),
+                };
+                Ok(())
+            }
+            Expr::Binary { left, oper, right } => {
+                self.expression(left)?;
+                self.expression(right)?;
+                self.span = oper.span;
+                self.binary(&oper.kind);
+                Ok(())
+            }
+            Expr::Call {
+                callee,
+                paren,
+                args,
+            } => {
+                self.expression(callee)?;
+                for arg in args {
+                    self.expression(arg)?;
+                }
+                self.span = paren.span;
+                let count = u8::try_from(args.len()).map_err(|_| {
+                    self.error("Can't have more than 255 arguments.")
+                })?;
+                self.emit(OpCode::Call(count));
+                Ok(())
+            }
+        }
+    }
+
+    // if the left operand is falsey it is the result of the whole
+    // expression and the right operand is never evaluated
+    fn and(&mut self, right: &Expr) -> Result<(), BessyError> {
+        let end_jump = self.emit(OpCode::JumpIfFalse(0));
+        self.emit(OpCode::Pop);
+        self.expression(right)?;
+        self.patch_jump(end_jump)
+    }
+
+    fn or(&mut self, right: &Expr) -> Result<(), BessyError> {
+        let else_jump = self.emit(OpCode::JumpIfFalse(0));
+        let end_jump = self.emit(OpCode::Jump(0));
+        self.patch_jump(else_jump)?;
+        self.emit(OpCode::Pop);
+        self.expression(right)?;
+        self.patch_jump(end_jump)
+    }
+
+    fn binary(&mut self, oper: &TokenType) {
+        match oper {
+            TokenType::Plus => self.emit(OpCode::Add),
+            TokenType::Minus => self.emit(OpCode::Subtract),
+            TokenType::Star => self.emit(OpCode::Multiply),
+            TokenType::Slash => self.emit(OpCode::Divide),
+            TokenType::Percent => self.emit(OpCode::Modulo),
+            TokenType::EqualEqual => self.emit(OpCode::Equal),
+            TokenType::Greater => self.emit(OpCode::Greater),
+            TokenType::Less => self.emit(OpCode::Less),
+            TokenType::BangEqual => {
+                self.emit(OpCode::Equal);
+                self.emit(OpCode::Not)
+            }
+            TokenType::GreaterEqual => {
+                self.emit(OpCode::Less);
+                self.emit(OpCode::Not)
+            }
+            TokenType::LessEqual => {
+                self.emit(OpCode::Greater);
+                self.emit(OpCode::Not)
+            }
+            _ => unreachable!(),
+        };
+    }
+}
@@ -0,0 +1,190 @@
+// the disassembler lists the instructions of a chunk in a form that
+// is close to the output of the reference implementation:
+//
+//   == <script> ==
+//   0000    1 Constant            0 '1'
+//   0001    | Print
+//
+// every instruction is printed on its own line with its offset, the
+// line it came from or a '|' if that is the same line as the one
+// before it, its name and its operands
+use crate::chunk::{Chunk, OpCode};
+use crate::heap::{Heap, ObjRef};
+use crate::object::Obj;
+use crate::value::Value;
+use std::fmt::Write;
+
+// lists the chunk of a function followed by the chunks of every
+// function that is declared inside of it
+pub fn disassemble_function(heap: &Heap, function: ObjRef) -> String {
+    let mut out = String::new();
+    let mut pending = vec![function];
+    while let Some(function) = pending.pop() {
+        let function = heap.function(function);
+        let name = function.name.map_or("<script>", |name| heap.string(name));
+        if !out.is_empty() {
+            out.push('\n');
+        }
+        out += &disassemble_chunk(heap, &function.chunk, name);
+        // the constants are pushed in reverse so that the functions
+        // are listed in the order they are declared in
+        pending.extend(function.chunk.constants.iter().rev().filter_map(
+            |constant| match constant {
+                Value::Obj(obj)
+                    if matches!(heap.get(*obj), Obj::Function(_)) =>
+                {
+                    Some(*obj)
+                }
+                _ => None,
+            },
+        ));
+    }
+    out
+}
+
+pub fn disassemble_chunk(heap: &Heap, chunk: &Chunk, name: &str) -> String {
+    let mut out = format!("== {name} ==\n");
+    for offset in 0..chunk.code.len() {
+        out += &disassemble_instruction(heap, chunk, offset);
+        out.push('\n');
+    }
+    out
+}
+
+pub fn disassemble_instruction(
+    heap: &Heap,
+    chunk: &Chunk,
+    offset: usize,
+) -> String {
+    let mut out = format!("{offset:04} ");
+    let line_number = chunk.spans[offset].start.line_number;
+    if offset > 0 && chunk.spans[offset - 1].start.line_number == line_number {
+        out += "   | ";
+    } else {
+        let _ = write!(out, "{line_number:4} ");
+    }
+
+    let constant = |index: u8| {
+        let value = chunk.constants[index as usize];
+        format!("{index:4} '{}'", value.display(heap))
+    };
+    let (name, operand) = match chunk.code[offset] {
+        OpCode::Constant(index) => ("Constant", constant(index)),
+        OpCode::Nil => ("Nil", String::new()),
+        OpCode::True => ("True", String::new()),
+        OpCode::False => ("False", String::new()),
+        OpCode::Pop => ("Pop", String::new()),
+        OpCode::GetLocal(slot) => ("GetLocal", format!("{slot:4}")),
+        OpCode::SetLocal(slot) => ("SetLocal", format!("{slot:4}")),
+        OpCode::GetUpvalue(index) => ("GetUpvalue", format!("{index:4}")),
+        OpCode::SetUpvalue(index) => ("SetUpvalue", format!("{index:4}")),
+        OpCode::GetGlobal(index) => ("GetGlobal", constant(index)),
+        OpCode::DefineGlobal(index) => ("DefineGlobal", constant(index)),
+        OpCode::SetGlobal(index) => ("SetGlobal", constant(index)),
+        OpCode::Equal => ("Equal", String::new()),
+        OpCode::Greater => ("Greater", String::new()),
+        OpCode::Less => ("Less", String::new()),
+        OpCode::Add => ("Add", String::new()),
+        OpCode::Subtract => ("Subtract", String::new()),
+        OpCode::Multiply => ("Multiply", String::new()),
+        OpCode::Divide => ("Divide", String::new()),
+        OpCode::Modulo => ("Modulo", String::new()),
+        OpCode::Not => ("Not", String::new()),
+        OpCode::Negate => ("Negate", String::new()),
+        OpCode::Stringify => ("Stringify", String::new()),
+        OpCode::Print => ("Print", String::new()),
+        // jumps are relative to the instruction after them
+        OpCode::Jump(jump) => (
+            "Jump",
+            format!("{offset:4} -> {}", offset + 1 + jump as usize),
+        ),
+        OpCode::JumpIfFalse(jump) => (
+            "JumpIfFalse",
+            format!("{offset:4} -> {}", offset + 1 + jump as usize),
+        ),
+        OpCode::Loop(jump) => (
+            "Loop",
+            format!("{offset:4} -> {}", offset + 1 - jump as usize),
+        ),
+        OpCode::Call(arg_count) => ("Call", format!("{arg_count:4}")),
+        OpCode::Closure(index) => {
+            let mut operand = constant(index);
+            let Value::Obj(function) = chunk.constants[index as usize] else {
+                unreachable!()
+            };
+            for capture in &heap.function(function).upvalues {
+                let kind = if capture.is_local { "local" } else { "upvalue" };
+                let _ = write!(
+                    operand,
+                    "\n{:04}    |                     {kind} {}",
+                    offset, capture.index
+                );
+            }
+            ("Closure", operand)
+        }
+        OpCode::CloseUpvalue => ("CloseUpvalue", String::new()),
+        OpCode::Class(index) => ("Class", constant(index)),
+        OpCode::Method(index) => ("Method", constant(index)),
+        OpCode::GetProperty(index) => ("GetProperty", constant(index)),
+        OpCode::SetProperty(index) => ("SetProperty", constant(index)),
+        OpCode::Inherit => ("Inherit", String::new()),
+        OpCode::GetSuper(index) => ("GetSuper", constant(index)),
+        OpCode::Return => ("Return", String::new()),
+    };
+    if operand.is_empty() {
+        out += name;
+    } else {
+        let _ = write!(out, "{name:<16} {operand}");
+    }
+    out
+}
+
+#[cfg(test)]
+mod test_debug {
+    #[test]
+    fn test_disassemble() {
+        assert_eq!(
+            crate::disassemble(
+                "fun f(a) {\n  if (a) return -a;\n}\nprint f(1);"
+            )
+            .unwrap(),
+            "== <script> ==\n\
+             0000    1 Closure             0 '<fn f>'\n\
+             0001    | DefineGlobal        1 'f'\n\
+             0002    4 GetGlobal           1 'f'\n\
+             0003    | Constant            2 '1'\n\
+             0004    | Call                1\n\
+             0005    | Print\n\
+             0006    | Nil\n\
+             0007    | Return\n\
+             \n\
+             == f ==\n\
+             0000    2 GetLocal            1\n\
+             0001    | JumpIfFalse         1 -> 7\n\
+             0002    | Pop\n\
+             0003    | GetLocal            1\n\
+             0004    | Negate\n\
+             0005    | Return\n\
+             0006    | Jump                6 -> 8\n\
+             0007    | Pop\n\
+             0008    | Nil\n\
+             0009    | Return\n"
+        );
+        // literals and statements take their line from their tokens
+        assert_eq!(
+            crate::disassemble("print 1;\nprint 2 +\n  3;\n\nnil;").unwrap(),
+            "== <script> ==\n\
+             0000    1 Constant            0 '1'\n\
+             0001    | Print\n\
+             0002    2 Constant            1 '2'\n\
+             0003    3 Constant            2 '3'\n\
+             0004    2 Add\n\
+             0005    | Print\n\
+             0006    5 Nil\n\
+             0007    | Pop\n\
+             0008    | Nil\n\
+             0009    | Return\n"
+        );
+        assert!(crate::disassemble("print a +;").is_err());
+    }
+}
@@ -1,5 +1,6 @@
-use crate::lexer::{Position, Span};
+use crate::lexer::{LineIndex, Position, Span};
 use std::fmt;
+use std::ops::Deref;
 
 impl fmt::Display for Position {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -13,20 +14,62 @@ impl fmt::Display for Position {
 
 impl fmt::Display for Span {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(
-            f,
-            "line {}, columns {} - {}",
-            self.start.line_number,
-            self.start.column_number,
-            self.end.column_number
-        )
+        if self.start.line_number == self.end.line_number {
+            write!(
+                f,
+                "line {}, columns {} - {}",
+                self.start.line_number,
+                self.start.column_number,
+                self.end.column_number
+            )
+        } else {
+            write!(f, "{} - {}", self.start, self.end)
+        }
     }
 }
 
 #[derive(Debug)]
 pub enum BessyError {
     UnterminatedString(Span),
-    Unexpected { msg: Box<str>, span: Option<Span> },
+    UnexpectedCharacter(char, Span),
+    InvalidEscape(Box<str>, Span),
+    InvalidNumber(Box<str>, Span),
+    UnterminatedComment(Span),
+    Unexpected {
+        msg: Box<str>,
+        span: Option<Span>,
+        labels: Vec<Label>,
+    },
+    Compile {
+        msg: Box<str>,
+        span: Span,
+    },
+    Runtime {
+        msg: Box<str>,
+        span: Span,
+        trace: Vec<TraceFrame>,
+    },
+    // a misuse of the embedding api that doesn't point at any source
+    Api {
+        msg: Box<str>,
+    },
+}
+
+// a call that was still running when a runtime error happened, the
+// function is None for the top level code of the script
+#[derive(Clone, Debug, PartialEq)]
+pub struct TraceFrame {
+    pub function: Option<Box<str>>,
+    pub line_number: usize,
+}
+
+impl fmt::Display for TraceFrame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.function {
+            Some(name) => write!(f, "[line {}] in {name}()", self.line_number),
+            None => write!(f, "[line {}] in script", self.line_number),
+        }
+    }
 }
 
 impl fmt::Display for BessyError {
@@ -39,15 +82,435 @@ impl fmt::Display for BessyError {
                     "Syntax Error: Unterminated string literal at {span}."
                 )
             }
-            Unexpected { msg, span } => {
+            UnexpectedCharacter(ch, span) => {
+                write!(
+                    f,
+                    "Syntax Error: Unexpected character '{ch}' at {span}."
+                )
+            }
+            InvalidEscape(escape, span) => {
+                write!(f, "Syntax Error: Invalid escape sequence ")?;
+                write!(f, "'{escape}' at {span}.")
+            }
+            InvalidNumber(lexeme, span) => {
+                write!(
+                    f,
+                    "Syntax Error: Invalid number literal '{lexeme}' at {span}."
+                )
+            }
+            UnterminatedComment(span) => {
+                write!(f, "Syntax Error: Unterminated block comment at {span}.")
+            }
+            Unexpected { msg, span, .. } => {
                 if let Some(span) = span {
                     write!(f, "Parse error: {msg} at {span}.")
                 } else {
                     write!(f, "Parse error: {msg} at end of file.")
                 }
             }
+            Compile { msg, span } => {
+                write!(f, "Compile error: {msg} at {span}.")
+            }
+            Runtime { msg, span, trace } => {
+                write!(f, "Runtime error: {msg} at {span}.")?;
+                for frame in trace {
+                    write!(f, "\n{frame}")?;
+                }
+                Ok(())
+            }
+            Api { msg } => write!(f, "API error: {msg}"),
         }
     }
 }
 
 impl std::error::Error for BessyError {}
+
+// the errors returned by the interpreter, parsing a program can find
+// more than one of them while running it stops at the first
+#[derive(Debug)]
+pub struct BessyErrors(Vec<BessyError>);
+
+impl BessyErrors {
+    pub fn render(&self, source: &str) -> String {
+        self.0.iter().map(|err| err.render(source)).collect()
+    }
+}
+
+impl From<BessyError> for BessyErrors {
+    fn from(err: BessyError) -> Self {
+        Self(vec![err])
+    }
+}
+
+impl From<Vec<BessyError>> for BessyErrors {
+    fn from(errors: Vec<BessyError>) -> Self {
+        Self(errors)
+    }
+}
+
+impl Deref for BessyErrors {
+    type Target = [BessyError];
+
+    fn deref(&self) -> &[BessyError] {
+        &self.0
+    }
+}
+
+impl IntoIterator for BessyErrors {
+    type Item = BessyError;
+    type IntoIter = std::vec::IntoIter<BessyError>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+impl fmt::Display for BessyErrors {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, err) in self.0.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{err}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for BessyErrors {}
+
+impl BessyError {
+    pub fn diagnostic(&self) -> Diagnostic {
+        use BessyError::*;
+        match self {
+            UnterminatedString(span) => {
+                Diagnostic::new("Syntax Error: Unterminated string literal.")
+                    .with_span(*span)
+                    .with_help("close the string with a '\"'")
+            }
+            UnexpectedCharacter(ch, span) => Diagnostic::new(&format!(
+                "Syntax Error: Unexpected character '{ch}'."
+            ))
+            .with_span(*span),
+            InvalidEscape(escape, span) => Diagnostic::new(&format!(
+                "Syntax Error: Invalid escape sequence '{escape}'."
+            ))
+            .with_span(*span)
+            .with_help(
+                "valid escapes are \\n, \\t, \\\", \\\\, \\$ and \\u{...}",
+            ),
+            InvalidNumber(lexeme, span) => Diagnostic::new(&format!(
+                "Syntax Error: Invalid number literal '{lexeme}'."
+            ))
+            .with_span(*span)
+            .with_help("numbers look like 42, 1_000, 1.5e-3, 0xff or 0b1010"),
+            UnterminatedComment(span) => {
+                Diagnostic::new("Syntax Error: Unterminated block comment.")
+                    .with_span(*span)
+                    .with_help("close the comment with a '*/'")
+            }
+            Unexpected { msg, span, labels } => {
+                let mut diagnostic =
+                    Diagnostic::new(&format!("Parse error: {msg}"));
+                diagnostic.labels.clone_from(labels);
+                match span {
+                    Some(span) => diagnostic.with_span(*span),
+                    None => diagnostic.with_note("reached the end of file"),
+                }
+            }
+            Compile { msg, span } => {
+                Diagnostic::new(&format!("Compile error: {msg}"))
+                    .with_span(*span)
+            }
+            Runtime { msg, span, .. } => {
+                Diagnostic::new(&format!("Runtime error: {msg}"))
+                    .with_span(*span)
+            }
+            Api { msg } => Diagnostic::new(&format!("API error: {msg}")),
+        }
+    }
+
+    // renders the error together with the lines of `source` it
+    // points at, an error at the end of file points just past the
+    // last character of the source and a runtime error is followed
+    // by the calls that led to it
+    pub fn render(&self, source: &str) -> String {
+        let mut diagnostic = self.diagnostic();
+        if let BessyError::Unexpected { span: None, .. } = self {
+            diagnostic.span = Some(end_of_source(source));
+        }
+        let mut out = diagnostic.render(source);
+        if let BessyError::Runtime { trace, .. } = self {
+            for frame in trace {
+                out += &format!("{frame}\n");
+            }
+        }
+        out
+    }
+}
+
+// a secondary location that helps to explain an error, like the
+// opening brace of a block that is never closed
+#[derive(Clone, Debug)]
+pub struct Label {
+    pub span: Span,
+    pub msg: Box<str>,
+}
+
+impl Label {
+    pub fn new(span: Span, msg: &str) -> Self {
+        Self {
+            span,
+            msg: msg.into(),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Diagnostic {
+    pub title: String,
+    pub span: Option<Span>,
+    pub labels: Vec<Label>,
+    pub notes: Vec<String>,
+    pub help: Option<String>,
+}
+
+impl Diagnostic {
+    pub fn new(title: &str) -> Self {
+        Self {
+            title: title.to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_span(mut self, span: Span) -> Self {
+        self.span = Some(span);
+        self
+    }
+
+    pub fn with_label(mut self, span: Span, msg: &str) -> Self {
+        self.labels.push(Label::new(span, msg));
+        self
+    }
+
+    pub fn with_note(mut self, note: &str) -> Self {
+        self.notes.push(note.to_string());
+        self
+    }
+
+    pub fn with_help(mut self, help: &str) -> Self {
+        self.help = Some(help.to_string());
+        self
+    }
+
+    // prints every line that is pointed at with the primary span
+    // underlined by carets and the labels underlined by dashes:
+    //
+    //   Parse error: Expect '}' after block.
+    //    --> line 3, column 8
+    //     |
+    //   1 | {
+    //     | - block opened here
+    //   ...
+    //   3 | print a
+    //     |        ^
+    //
+    // a span over several lines underlines every line it covers
+    pub fn render(&self, source: &str) -> String {
+        let lines: Vec<&str> = source.lines().collect();
+        let mut marks: Vec<(Span, char, &str)> = self
+            .labels
+            .iter()
+            .map(|label| (label.span, '-', &*label.msg))
+            .collect();
+        if let Some(span) = self.span {
+            marks.push((span, '^', ""));
+        }
+        // a span that doesn't point into the source, like one made up
+        // by the host, is only shown in the title
+        marks.retain(|(span, ..)| {
+            (1..=lines.len()).contains(&span.start.line_number)
+        });
+        marks.sort_by_key(|(span, ..)| {
+            (span.start.line_number, span.start.column_number)
+        });
+
+        let width = marks
+            .iter()
+            .map(|(span, ..)| span.end.line_number.to_string().len())
+            .max()
+            .unwrap_or(1);
+        let blank = " ".repeat(width);
+
+        let mut out = format!("{}\n", self.title);
+        if let Some(span) = self.span {
+            out += &format!("{blank}--> {}\n", span.start);
+        }
+        if !marks.is_empty() {
+            out += &format!("{blank} |\n");
+        }
+        let mut previous_line = None;
+        for (span, mark, msg) in &marks {
+            let first = span.start.line_number;
+            let last = span.end.line_number.max(first);
+            for line_number in first..=last {
+                let Some(line) = line_number
+                    .checked_sub(1)
+                    .and_then(|index| lines.get(index))
+                else {
+                    break;
+                };
+                if previous_line.is_none_or(|prev| line_number > prev) {
+                    if previous_line.is_some_and(|prev| line_number > prev + 1)
+                    {
+                        out += "...\n";
+                    }
+                    out += &format!("{line_number:>width$} | {line}\n");
+                    previous_line = Some(line_number);
+                }
+                let start = if line_number == first {
+                    span.start.column_number
+                } else {
+                    0
+                };
+                let (end, msg) = if line_number == last {
+                    (span.end.column_number, *msg)
+                } else {
+                    (line.chars().count(), "")
+                };
+                let underline = underline(line, start, end, *mark, msg);
+                out += &format!("{blank} | {underline}\n");
+            }
+        }
+        for note in &self.notes {
+            out += &format!("{blank} = note: {note}\n");
+        }
+        if let Some(help) = &self.help {
+            out += &format!("{blank} = help: {help}\n");
+        }
+        out
+    }
+}
+
+fn end_of_source(source: &str) -> Span {
+    let end = LineIndex::new(source).position(source.trim_end().len());
+    Span { start: end, end }
+}
+
+// columns are counted in characters, the underline keeps the tabs
+// of the line so that it is lined up the same way the line is
+fn underline(
+    line: &str,
+    start: usize,
+    end: usize,
+    mark: char,
+    msg: &str,
+) -> String {
+    let mut underline: String = line
+        .chars()
+        .take(start)
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect();
+    let length = end.saturating_sub(start).max(1);
+    underline.extend(std::iter::repeat_n(mark, length));
+    if !msg.is_empty() {
+        underline.push(' ');
+        underline.push_str(msg);
+    }
+    underline
+}
+
+#[cfg(test)]
+mod test_error {
+    use super::*;
+
+    fn span(line_number: usize, start: usize, end: usize) -> Span {
+        let position = |column_number| Position {
+            offset: 0,
+            line_number,
+            column_number,
+        };
+        Span {
+            start: position(start),
+            end: position(end),
+        }
+    }
+
+    #[test]
+    fn test_render() {
+        let diagnostic =
+            Diagnostic::new("Parse error: Expect '}' after block.")
+                .with_span(span(3, 9, 10))
+                .with_label(span(1, 0, 1), "block opened here")
+                .with_help("add a '}'");
+        assert_eq!(
+            diagnostic.render("{\n  var a = 1;\n  print a;\n"),
+            "Parse error: Expect '}' after block.\n \
+             --> line 3, column 9\n  \
+             |\n\
+             1 | {\n  \
+             | - block opened here\n\
+             ...\n\
+             3 |   print a;\n  \
+             |          ^\n  \
+             = help: add a '}'\n"
+        );
+    }
+
+    #[test]
+    fn test_render_tabs() {
+        let diagnostic =
+            Diagnostic::new("Runtime error: Oops.").with_span(span(1, 7, 10));
+        assert_eq!(
+            diagnostic.render("\tprint \"ä\";"),
+            "Runtime error: Oops.\n \
+             --> line 1, column 7\n  \
+             |\n\
+             1 | \tprint \"ä\";\n  \
+             | \t      ^^^\n"
+        );
+    }
+
+    #[test]
+    fn test_render_outside_source() {
+        let diagnostic =
+            Diagnostic::new("Runtime error: Oops.").with_span(span(0, 0, 0));
+        assert_eq!(
+            diagnostic.render("print 1;"),
+            "Runtime error: Oops.\n \
+             --> line 0, column 0\n"
+        );
+        let diagnostic =
+            Diagnostic::new("Runtime error: Oops.").with_span(span(2, 0, 1));
+        assert_eq!(
+            diagnostic.render("print 1;"),
+            "Runtime error: Oops.\n \
+             --> line 2, column 0\n"
+        );
+    }
+
+    #[test]
+    fn test_render_multiline() {
+        let source = "print \"one\ntwo\nthree\";";
+        let index = LineIndex::new(source);
+        let span = Span {
+            start: index.position(6),
+            end: index.position(21),
+        };
+        assert_eq!(format!("{span}"), "line 1, column 6 - line 3, column 6");
+        assert_eq!(
+            Diagnostic::new("Runtime error: Oops.")
+                .with_span(span)
+                .render(source),
+            "Runtime error: Oops.\n \
+             --> line 1, column 6\n  \
+             |\n\
+             1 | print \"one\n  \
+             |       ^^^^\n\
+             2 | two\n  \
+             | ^^^\n\
+             3 | three\";\n  \
+             | ^^^^^^\n"
+        );
+    }
+}
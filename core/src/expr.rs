@@ -1,4 +1,4 @@
-use crate::lexer::Token;
+use crate::lexer::{Span, Token};
 
 #[derive(Clone, Debug)]
 pub enum Expr {
@@ -8,22 +8,43 @@ pub enum Expr {
         oper: Token,
         right: Box<Expr>,
     },
+    Logical {
+        left: Box<Expr>,
+        oper: Token,
+        right: Box<Expr>,
+    },
     Unary {
         oper: Token,
         right: Box<Expr>,
     },
-    Number(f64),
-    Boolean(bool),
-    String(String),
+    Number(f64, Span),
+    Boolean(bool, Span),
+    String(String, Span),
     Assign {
         name: Token,
         value: Box<Expr>,
     },
     Group(Box<Expr>),
+    // turns the value of an interpolated expression into a string
+    Stringify(Box<Expr>),
     Call {
         callee: Box<Expr>,
         paren: Token,
         args: Vec<Expr>,
     },
-    Nil,
+    Get {
+        object: Box<Expr>,
+        name: Token,
+    },
+    Set {
+        object: Box<Expr>,
+        name: Token,
+        value: Box<Expr>,
+    },
+    This(Token),
+    Super {
+        keyword: Token,
+        method: Token,
+    },
+    Nil(Span),
 }
@@ -0,0 +1,332 @@
+// the heap owns every object created while compiling and running a
+// program, values only hold a handle to the object they refer to
+use crate::chunk::OpCode;
+use crate::lexer::Span;
+use crate::object::{
+    Obj, ObjClass, ObjClosure, ObjFunction, ObjInstance, ObjString, ObjUpvalue,
+    Upvalue,
+};
+use crate::value::Value;
+use std::collections::HashMap;
+use std::mem::size_of;
+use std::rc::Rc;
+
+const INITIAL_GC_THRESHOLD: usize = 1024 * 1024;
+const GC_HEAP_GROW_FACTOR: usize = 2;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ObjRef(usize);
+
+#[derive(Debug)]
+pub struct Heap {
+    // a slot is `None` once the object in it has been collected and
+    // is reused by the next allocation
+    objects: Vec<Option<Obj>>,
+    marks: Vec<bool>,
+    free: Vec<usize>,
+    // strings are interned so that comparing two of them only needs
+    // a comparison of their handles
+    strings: HashMap<Rc<str>, ObjRef>,
+    gray: Vec<ObjRef>,
+    bytes_allocated: usize,
+    next_gc: usize,
+    // collect garbage before every allocation to shake out objects
+    // that are in use but not reachable from any root
+    pub stress: bool,
+}
+
+impl Default for Heap {
+    fn default() -> Self {
+        Self {
+            objects: Vec::new(),
+            marks: Vec::new(),
+            free: Vec::new(),
+            strings: HashMap::new(),
+            gray: Vec::new(),
+            bytes_allocated: 0,
+            next_gc: INITIAL_GC_THRESHOLD,
+            stress: cfg!(feature = "stress-gc"),
+        }
+    }
+}
+
+// an estimate of the memory owned by an object, growth of an object
+// after it was allocated is accounted for at the next collection
+fn size_of_obj(obj: &Obj) -> usize {
+    size_of::<Obj>()
+        + match obj {
+            Obj::String(string) => string.value.len(),
+            Obj::Function(function) => {
+                let chunk = &function.chunk;
+                chunk.code.capacity() * size_of::<OpCode>()
+                    + chunk.spans.capacity() * size_of::<Span>()
+                    + chunk.constants.capacity() * size_of::<Value>()
+                    + function.upvalues.capacity() * size_of::<Upvalue>()
+            }
+            Obj::Closure(closure) => {
+                closure.upvalues.capacity() * size_of::<ObjRef>()
+            }
+            Obj::Class(class) => {
+                class.methods.capacity() * size_of::<(ObjRef, ObjRef)>()
+            }
+            Obj::Instance(instance) => {
+                instance.fields.capacity() * size_of::<(ObjRef, Value)>()
+            }
+            Obj::Native(_) | Obj::BoundMethod(_) | Obj::Upvalue(_) => 0,
+        }
+}
+
+impl Heap {
+    pub fn alloc(&mut self, obj: Obj) -> ObjRef {
+        self.bytes_allocated += size_of_obj(&obj);
+        if let Some(index) = self.free.pop() {
+            self.objects[index] = Some(obj);
+            ObjRef(index)
+        } else {
+            self.objects.push(Some(obj));
+            self.marks.push(false);
+            ObjRef(self.objects.len() - 1)
+        }
+    }
+
+    pub fn intern(&mut self, value: &str) -> ObjRef {
+        if let Some(&string) = self.strings.get(value) {
+            return string;
+        }
+        let value: Rc<str> = Rc::from(value);
+        let string = self.alloc(Obj::String(ObjString {
+            value: Rc::clone(&value),
+        }));
+        self.strings.insert(value, string);
+        string
+    }
+
+    // finds an interned string without allocating it
+    pub fn lookup(&self, value: &str) -> Option<ObjRef> {
+        self.strings.get(value).copied()
+    }
+
+    pub fn get(&self, obj: ObjRef) -> &Obj {
+        self.objects[obj.0].as_ref().unwrap()
+    }
+
+    pub fn get_mut(&mut self, obj: ObjRef) -> &mut Obj {
+        self.objects[obj.0].as_mut().unwrap()
+    }
+
+    pub fn string(&self, obj: ObjRef) -> &str {
+        match self.get(obj) {
+            Obj::String(string) => &string.value,
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn function(&self, obj: ObjRef) -> &ObjFunction {
+        match self.get(obj) {
+            Obj::Function(function) => function,
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn closure(&self, obj: ObjRef) -> &ObjClosure {
+        match self.get(obj) {
+            Obj::Closure(closure) => closure,
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn closure_mut(&mut self, obj: ObjRef) -> &mut ObjClosure {
+        match self.get_mut(obj) {
+            Obj::Closure(closure) => closure,
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn upvalue(&self, obj: ObjRef) -> &ObjUpvalue {
+        match self.get(obj) {
+            Obj::Upvalue(upvalue) => upvalue,
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn upvalue_mut(&mut self, obj: ObjRef) -> &mut ObjUpvalue {
+        match self.get_mut(obj) {
+            Obj::Upvalue(upvalue) => upvalue,
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn class(&self, obj: ObjRef) -> &ObjClass {
+        match self.get(obj) {
+            Obj::Class(class) => class,
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn class_mut(&mut self, obj: ObjRef) -> &mut ObjClass {
+        match self.get_mut(obj) {
+            Obj::Class(class) => class,
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn instance_mut(&mut self, obj: ObjRef) -> &mut ObjInstance {
+        match self.get_mut(obj) {
+            Obj::Instance(instance) => instance,
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn should_collect(&self) -> bool {
+        self.stress || self.bytes_allocated > self.next_gc
+    }
+
+    pub fn mark_value(&mut self, value: Value) {
+        if let Value::Obj(obj) = value {
+            self.mark_object(obj);
+        }
+    }
+
+    pub fn mark_object(&mut self, obj: ObjRef) {
+        if !self.marks[obj.0] {
+            self.marks[obj.0] = true;
+            self.gray.push(obj);
+        }
+    }
+
+    // marks every object referred to by `obj`, which is also how an
+    // object that is about to be allocated keeps its references alive
+    pub fn mark_references(&mut self, obj: &Obj) {
+        match obj {
+            Obj::String(_) => {}
+            Obj::Function(function) => {
+                if let Some(name) = function.name {
+                    self.mark_object(name);
+                }
+                for &constant in &function.chunk.constants {
+                    self.mark_value(constant);
+                }
+            }
+            Obj::Closure(closure) => {
+                self.mark_object(closure.function);
+                for &upvalue in &closure.upvalues {
+                    self.mark_object(upvalue);
+                }
+                if let Some(class) = closure.class {
+                    self.mark_object(class);
+                }
+            }
+            Obj::Native(native) => self.mark_object(native.name),
+            Obj::Class(class) => {
+                self.mark_object(class.name);
+                if let Some(superclass) = class.superclass {
+                    self.mark_object(superclass);
+                }
+                for (&name, &method) in &class.methods {
+                    self.mark_object(name);
+                    self.mark_object(method);
+                }
+            }
+            Obj::Instance(instance) => {
+                self.mark_object(instance.class);
+                for (&name, &value) in &instance.fields {
+                    self.mark_object(name);
+                    self.mark_value(value);
+                }
+            }
+            Obj::BoundMethod(bound) => {
+                self.mark_value(bound.receiver);
+                self.mark_object(bound.method);
+            }
+            Obj::Upvalue(ObjUpvalue::Closed(value)) => self.mark_value(*value),
+            Obj::Upvalue(ObjUpvalue::Open(_)) => {}
+        }
+    }
+
+    // traces everything reachable from the objects marked so far
+    // and frees the rest, the caller is expected to have marked all
+    // of its roots before calling this
+    pub fn collect(&mut self) {
+        while let Some(obj) = self.gray.pop() {
+            // the object is taken out of its slot while its references
+            // are marked and put back right after
+            let object = self.objects[obj.0].take().unwrap();
+            self.mark_references(&object);
+            self.objects[obj.0] = Some(object);
+        }
+
+        let marks = &self.marks;
+        self.strings.retain(|_, string| marks[string.0]);
+
+        self.bytes_allocated = 0;
+        for (index, slot) in self.objects.iter_mut().enumerate() {
+            if self.marks[index] {
+                self.marks[index] = false;
+                self.bytes_allocated += slot.as_ref().map_or(0, size_of_obj);
+            } else if slot.take().is_some() {
+                self.free.push(index);
+            }
+        }
+        self.next_gc = (self.bytes_allocated * GC_HEAP_GROW_FACTOR)
+            .max(INITIAL_GC_THRESHOLD);
+    }
+}
+
+#[cfg(test)]
+mod test_heap {
+    use super::*;
+    use crate::object::ObjFunction;
+
+    #[test]
+    fn test_collect() {
+        let mut heap = Heap::default();
+        let name = heap.intern("f");
+        let constant = heap.intern("constant");
+        let mut function = ObjFunction {
+            name: Some(name),
+            ..Default::default()
+        };
+        function.chunk.add_constant(Value::Obj(constant));
+        let function = heap.alloc(Obj::Function(function));
+        let closure = heap.alloc(Obj::Closure(ObjClosure {
+            function,
+            upvalues: Vec::new(),
+            class: None,
+        }));
+        let garbage: Vec<ObjRef> = (0..100)
+            .map(|i| heap.intern(&format!("garbage {i}")))
+            .collect();
+
+        heap.mark_object(closure);
+        heap.collect();
+
+        // everything reachable from the closure survives
+        assert_eq!(heap.closure(closure).function, function);
+        assert_eq!(heap.function(function).name, Some(name));
+        assert_eq!(heap.string(name), "f");
+        assert_eq!(heap.lookup("constant"), Some(constant));
+        // the rest is freed and its slots are reused
+        assert_eq!(heap.lookup("garbage 0"), None);
+        assert_eq!(heap.free.len(), garbage.len());
+        assert!(garbage.iter().all(|obj| heap.objects[obj.0].is_none()));
+        let reused = heap.intern("new");
+        assert!(garbage.contains(&reused));
+        assert_eq!(heap.objects.len(), 104);
+    }
+
+    #[test]
+    fn test_next_gc() {
+        let mut heap = Heap::default();
+        let big = heap.intern(&"x".repeat(INITIAL_GC_THRESHOLD));
+        heap.mark_object(big);
+        heap.collect();
+        // the threshold grows with the memory that is still in use
+        assert!(heap.bytes_allocated > INITIAL_GC_THRESHOLD);
+        assert_eq!(heap.next_gc, heap.bytes_allocated * GC_HEAP_GROW_FACTOR);
+
+        heap.collect();
+        assert_eq!(heap.bytes_allocated, 0);
+        assert_eq!(heap.next_gc, INITIAL_GC_THRESHOLD);
+        assert_eq!(heap.lookup(&"x".repeat(INITIAL_GC_THRESHOLD)), None);
+    }
+}
@@ -0,0 +1,261 @@
+// the interpreter is the entry point for programs that embed bessy,
+// it holds on to a virtual machine so that the globals defined by one
+// program are still there for the next one and for the host
+use crate::error::BessyErrors;
+use crate::stmt::Stmt;
+use crate::value::HostValue;
+use crate::vm::{Options, Vm};
+use std::io::Write;
+use std::rc::Rc;
+
+/// Runs Lox programs and lets the host read and write their global
+/// variables, call their functions and give them native functions.
+///
+/// ```
+/// use core::{BessyErrors, HostValue, Interpreter};
+///
+/// fn main() -> Result<(), BessyErrors> {
+///     let mut interpreter = Interpreter::new();
+///     let mut output = Vec::new();
+///     interpreter.evaluate("fun double(n) { return n * 2; }", &mut output)?;
+///     let result =
+///         interpreter.call_function("double", &[21.0.into()], &mut output)?;
+///     assert_eq!(result, HostValue::Number(42.0));
+///     Ok(())
+/// }
+/// ```
+pub struct Interpreter {
+    vm: Vm,
+}
+
+/// A program that has been compiled by [`Interpreter::compile`] and
+/// can be run by the same interpreter as many times as needed.
+#[derive(Debug)]
+pub struct Program {
+    stmts: Vec<Stmt>,
+    // the bytecode stays with the interpreter until this is dropped
+    handle: Rc<()>,
+}
+
+impl Program {
+    /// The syntax tree of the program.
+    pub fn stmts(&self) -> &[Stmt] {
+        &self.stmts
+    }
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Interpreter {
+    pub fn new() -> Self {
+        Self::with_options(Options::default())
+    }
+
+    pub fn with_options(options: Options) -> Self {
+        Self {
+            vm: Vm::with_options(options),
+        }
+    }
+
+    /// Compiles `source` to bytecode without running it, the program
+    /// it returns can be inspected and then passed to
+    /// [`Interpreter::run`].
+    pub fn compile(&mut self, source: &str) -> Result<Program, BessyErrors> {
+        let stmts = crate::parse_program(source)?;
+        let handle = self.vm.compile(&stmts)?;
+        Ok(Program { stmts, handle })
+    }
+
+    /// Runs a program compiled by this interpreter, what it prints is
+    /// written to `output`.
+    pub fn run(
+        &mut self,
+        program: &Program,
+        output: &mut impl Write,
+    ) -> Result<(), BessyErrors> {
+        Ok(self.vm.run_compiled(&program.handle, output)?)
+    }
+
+    /// Compiles and runs `source`.
+    pub fn evaluate(
+        &mut self,
+        source: &str,
+        output: &mut impl Write,
+    ) -> Result<(), BessyErrors> {
+        let program = self.compile(source)?;
+        self.run(&program, output)
+    }
+
+    /// Calls a global function declared by a program that has been run
+    /// and returns the value it returned.
+    pub fn call_function(
+        &mut self,
+        name: &str,
+        args: &[HostValue],
+        output: &mut impl Write,
+    ) -> Result<HostValue, BessyErrors> {
+        Ok(self.vm.call_function(name, args, output)?)
+    }
+
+    pub fn get_global(&self, name: &str) -> Option<HostValue> {
+        self.vm.get_global(name)
+    }
+
+    /// Defines or overwrites a global variable, only nil, booleans,
+    /// numbers and strings can be given to Lox.
+    pub fn set_global(
+        &mut self,
+        name: &str,
+        value: impl Into<HostValue>,
+    ) -> Result<(), BessyErrors> {
+        Ok(self.vm.set_global(name, &value.into())?)
+    }
+
+    /// Defines a global function that runs `function` on the host, an
+    /// error it returns becomes a runtime error at the call.
+    pub fn register_native(
+        &mut self,
+        name: &str,
+        arity: usize,
+        function: impl Fn(&[HostValue]) -> Result<HostValue, String> + 'static,
+    ) {
+        self.vm.define_native(name, arity, Rc::new(function));
+    }
+}
+
+#[cfg(test)]
+mod test_interpreter {
+    use super::*;
+
+    #[test]
+    fn test_globals() {
+        let mut interpreter = Interpreter::new();
+        let mut output = Vec::new();
+        interpreter.set_global("name", "lox").unwrap();
+        interpreter
+            .evaluate(
+                "var greeting = \"hello \" + name; fun f() {}",
+                &mut output,
+            )
+            .unwrap();
+        assert_eq!(
+            interpreter.get_global("greeting"),
+            Some(HostValue::from("hello lox"))
+        );
+        assert_eq!(interpreter.get_global("missing"), None);
+        let f = interpreter.get_global("f").unwrap();
+        assert_eq!(f, HostValue::Object("<fn f>".into()));
+        assert!(interpreter.set_global("g", f).is_err());
+    }
+
+    #[test]
+    fn test_call_function() {
+        let mut interpreter = Interpreter::new();
+        let mut output = Vec::new();
+        interpreter
+            .evaluate(
+                "var calls = 0; \
+                 fun add(a, b) { calls = calls + 1; print a + b; \
+                 return a + b; }",
+                &mut output,
+            )
+            .unwrap();
+        let args = [HostValue::from("a"), HostValue::from("b")];
+        assert_eq!(
+            interpreter
+                .call_function("add", &args, &mut output)
+                .unwrap(),
+            HostValue::from("ab")
+        );
+        assert_eq!(output, b"ab\n");
+        assert_eq!(interpreter.get_global("calls"), Some(1.0.into()));
+        assert!(interpreter.call_function("add", &[], &mut output).is_err());
+        assert!(interpreter
+            .call_function("calls", &[], &mut output)
+            .is_err());
+        assert!(interpreter.call_function("none", &[], &mut output).is_err());
+        // a runtime error leaves the interpreter ready for the next call
+        let args = [HostValue::Nil, HostValue::Nil];
+        assert!(interpreter
+            .call_function("add", &args, &mut output)
+            .is_err());
+        let args = [1.0.into(), 2.0.into()];
+        assert_eq!(
+            interpreter
+                .call_function("add", &args, &mut output)
+                .unwrap(),
+            HostValue::Number(3.0)
+        );
+    }
+
+    #[test]
+    fn test_error_closes_upvalues() {
+        let mut interpreter = Interpreter::new();
+        let mut output = Vec::new();
+        assert!(interpreter
+            .evaluate(
+                "var f; fun make() { var x = 1; fun g() { return x; } \
+                 f = g; return nil + 1; } make();",
+                &mut output,
+            )
+            .is_err());
+        interpreter.evaluate("print f();", &mut output).unwrap();
+        assert_eq!(output, b"1\n");
+    }
+
+    #[test]
+    fn test_register_native() {
+        let mut interpreter = Interpreter::new();
+        let mut output = Vec::new();
+        interpreter.register_native("shout", 1, |args| match &args[0] {
+            HostValue::String(string) => {
+                Ok(HostValue::from(&*string.to_uppercase()))
+            }
+            _ => Err(String::from("Argument must be a string.")),
+        });
+        interpreter
+            .evaluate("print shout(\"hi\");", &mut output)
+            .unwrap();
+        assert_eq!(output, b"HI\n");
+        let errors = interpreter.evaluate("shout(1);", &mut output);
+        assert_eq!(
+            errors.unwrap_err()[0].to_string(),
+            "Runtime error: Argument must be a string. \
+             at line 1, columns 7 - 8.\n[line 1] in script"
+        );
+    }
+
+    #[test]
+    fn test_compile() {
+        let mut interpreter = Interpreter::new();
+        let program = interpreter
+            .compile("print \"compiled\"; { var a = 2; }")
+            .unwrap();
+        assert!(matches!(
+            program.stmts(),
+            [Stmt::Print { .. }, Stmt::Block(_)]
+        ));
+        let mut output = Vec::new();
+        interpreter.run(&program, &mut output).unwrap();
+        // enough garbage for a collection to run before the program
+        // is run again
+        interpreter
+            .evaluate(
+                "var s = \"\"; for (var i = 0; i < 1000; i = i + 1) { \
+                 s = s + \"0123456789\"; }",
+                &mut output,
+            )
+            .unwrap();
+        interpreter.run(&program, &mut output).unwrap();
+        assert_eq!(output, b"compiled\ncompiled\n");
+        assert!(Interpreter::new().run(&program, &mut output).is_err());
+        assert!(interpreter.compile("return 1;").is_err());
+        let errors = interpreter.compile("print ;\nvar 1;").unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors.to_string(), format!("{}\n{}", errors[0], errors[1]));
+    }
+}
@@ -1,6 +1,10 @@
-use crate::error::BessyError;
+use crate::error::{BessyError, BessyErrors};
+use std::cell::Cell;
+use std::collections::VecDeque;
 use std::iter::Peekable;
 use std::str::CharIndices;
+#[cfg(feature = "unicode-xid")]
+use unicode_xid::UnicodeXID;
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum TokenType {
@@ -28,9 +32,10 @@ pub enum TokenType {
 
     Number(f64),
     Boolean(bool),
-    Unknown,
     Identifier(String),
     StringLiteral(String),
+    // the part of a string before an interpolated expression
+    Interpolation(String),
     Print,
     Var,
     Nil,
@@ -42,6 +47,11 @@ pub enum TokenType {
     Return,
     And,
     Or,
+    Class,
+    This,
+    Super,
+    // stands in for text the lexer reported an error for
+    Invalid,
 }
 
 impl std::fmt::Display for TokenType {
@@ -70,9 +80,11 @@ impl std::fmt::Display for TokenType {
             LessEqual => write!(f, "<="),
             Number(num) => write!(f, "number: {num}"),
             Boolean(value) => write!(f, "boolean: {value}"),
-            Unknown => write!(f, "unknown token"),
             Identifier(name) => write!(f, "identifier: {name}"),
             StringLiteral(lexeme) => write!(f, "string: {lexeme}"),
+            Interpolation(lexeme) => {
+                write!(f, "interpolated string: {lexeme}")
+            }
             Print => write!(f, "keyword print"),
             Var => write!(f, "keyword var"),
             Nil => write!(f, "keyword nil"),
@@ -84,10 +96,13 @@ impl std::fmt::Display for TokenType {
             Return => write!(f, "keyword return"),
             And => write!(f, "keyword and"),
             Or => write!(f, "keyword or"),
+            Class => write!(f, "keyword class"),
+            This => write!(f, "keyword this"),
+            Super => write!(f, "keyword super"),
+            Invalid => write!(f, "invalid token"),
         }
     }
 }
-    
 
 #[derive(Clone, Debug)]
 pub struct Token {
@@ -95,16 +110,134 @@ pub struct Token {
     pub span: Span,
 }
 
-#[derive(Clone, Copy, Debug)]
+// text between tokens that doesn't change the meaning of a program
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TriviaKind {
+    Whitespace,
+    Newline,
+    Comment,
+    // text that could not be scanned into a token, like a character
+    // that is not part of the language
+    Error,
+}
+
+#[derive(Clone, Debug)]
+pub struct Trivia {
+    pub kind: TriviaKind,
+    pub span: Span,
+}
+
+// a token together with the trivia that comes right before it
+#[derive(Clone, Debug)]
+pub struct TriviaToken {
+    pub leading: Vec<Trivia>,
+    pub token: Token,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
 pub struct Span {
     pub start: Position,
     pub end: Position,
 }
 
-#[derive(Clone, Copy, Debug)]
+// lines are counted from one and columns from zero, the column is
+// the number of characters between the start of the line and `offset`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
 pub struct Position {
-    pub line_number: u16,
-    pub column_number: u16,
+    pub offset: usize,
+    pub line_number: usize,
+    pub column_number: usize,
+}
+
+// the byte offset of the start of every line in a text, used to
+// map between byte offsets and lines and columns
+#[derive(Clone, Debug)]
+pub struct LineIndex<'src> {
+    text: &'src str,
+    line_starts: Vec<usize>,
+    // the last offset a column was counted for and its column, the
+    // lexer asks for positions from left to right so only the text
+    // after the last one has to be counted
+    last_column: Cell<(usize, usize)>,
+}
+
+impl<'src> LineIndex<'src> {
+    pub fn new(text: &'src str) -> Self {
+        let mut line_starts = vec![0];
+        line_starts
+            .extend(text.match_indices('\n').map(|(index, _)| index + 1));
+        Self {
+            text,
+            line_starts,
+            last_column: Cell::new((0, 0)),
+        }
+    }
+
+    // the text between the start of the line `offset` is on and
+    // `offset` itself
+    fn line_prefix(&self, offset: usize) -> (usize, &'src str) {
+        let line_number =
+            self.line_starts.partition_point(|&start| start <= offset);
+        let start = self.line_starts[line_number - 1];
+        (line_number, &self.text[start..offset])
+    }
+
+    pub fn position(&self, offset: usize) -> Position {
+        let (line_number, prefix) = self.line_prefix(offset);
+        let start = offset - prefix.len();
+        let (last_offset, last_column) = self.last_column.get();
+        let column_number = if (start..=offset).contains(&last_offset) {
+            last_column + self.text[last_offset..offset].chars().count()
+        } else {
+            prefix.chars().count()
+        };
+        self.last_column.set((offset, column_number));
+        Position {
+            offset,
+            line_number,
+            column_number,
+        }
+    }
+
+    // the column counted in UTF-16 code units, which is how editors
+    // running in the browser count them
+    pub fn utf16_column(&self, offset: usize) -> usize {
+        self.line_prefix(offset).1.encode_utf16().count()
+    }
+
+    pub fn offset(
+        &self,
+        line_number: usize,
+        column_number: usize,
+    ) -> Option<usize> {
+        let start = *self.line_starts.get(line_number.checked_sub(1)?)?;
+        let line = self.text[start..].split('\n').next().unwrap_or_default();
+        line.char_indices()
+            .map(|(index, _)| index)
+            .chain(std::iter::once(line.len()))
+            .nth(column_number)
+            .map(|index| start + index)
+    }
+}
+
+#[cfg(feature = "unicode-xid")]
+fn is_identifier_start(c: char) -> bool {
+    c == '_' || c.is_xid_start()
+}
+
+#[cfg(feature = "unicode-xid")]
+fn is_identifier_continue(c: char) -> bool {
+    c.is_xid_continue()
+}
+
+#[cfg(not(feature = "unicode-xid"))]
+fn is_identifier_start(c: char) -> bool {
+    c == '_' || c.is_ascii_alphabetic()
+}
+
+#[cfg(not(feature = "unicode-xid"))]
+fn is_identifier_continue(c: char) -> bool {
+    c == '_' || c.is_ascii_alphanumeric()
 }
 
 impl Token {
@@ -118,84 +251,162 @@ impl Token {
 }
 
 pub struct Lexer<'src> {
+    text: &'src str,
     cursor: Peekable<CharIndices<'src>>,
-    tokens: Vec<Token>,
-    line: u16,
-    column: u16,
-    start_of_line: u16,
+    // tokens and errors that have been scanned but not handed out
+    pending: VecDeque<Result<Token, BessyError>>,
+    index: LineIndex<'src>,
+    // the start of the string and the depth of nested braces of every
+    // interpolated expression being scanned, innermost last
+    interpolations: Vec<(usize, usize)>,
+    // only recorded when scanning with trivia
+    trivia: Option<Vec<Trivia>>,
 }
 
 impl<'src> Lexer<'src> {
     pub fn new(text: &'src str) -> Self {
         Self {
             cursor: text.char_indices().peekable(),
-            line: 1,
-            column: 0,
-            start_of_line: 0,
-            tokens: Vec::with_capacity(text.len()),
+            text,
+            index: LineIndex::new(text),
+            interpolations: Vec::new(),
+            trivia: None,
+            pending: VecDeque::new(),
         }
     }
 
-    fn make_span(&mut self, start: usize, end: usize) -> Span {
-        let end = start + end;
-        let start_column = self.column(start);
-        let end_column = self.column(end);
+    fn make_span(&self, start: usize, len: usize) -> Span {
+        Span {
+            start: self.index.position(start),
+            end: self.index.position(start + len),
+        }
+    }
 
-        let start = Position {
-            line_number: self.line,
-            column_number: start_column,
-        };
+    // records the text from `start` up to the next character as trivia
+    fn add_trivia(&mut self, kind: TriviaKind, start: usize) {
+        if self.trivia.is_none() {
+            return;
+        }
+        let end = self.cursor.peek().map_or(self.text.len(), |x| x.0);
+        let span = self.make_span(start, end - start);
+        if let Some(trivia) = &mut self.trivia {
+            trivia.push(Trivia { kind, span });
+        }
+    }
 
-        let end = Position {
-            line_number: self.line,
-            column_number: end_column,
-        };
+    // like `scan_all` but keeps the whitespace and comments between the
+    // tokens, the trivia and tokens that are returned followed by the
+    // trailing trivia add up to the text byte for byte
+    pub fn scan_with_trivia(
+        &mut self,
+    ) -> (Vec<TriviaToken>, Vec<Trivia>, BessyErrors) {
+        self.trivia = Some(Vec::new());
+        let (tokens, errors) = self.scan_all();
+        let mut trivia = self.trivia.take().unwrap().into_iter().peekable();
+        let mut trivia_tokens = Vec::with_capacity(tokens.len());
+        for token in tokens {
+            let mut leading = Vec::new();
+            while let Some(item) = trivia
+                .next_if(|item| item.span.end.offset <= token.span.start.offset)
+            {
+                leading.push(item);
+            }
+            trivia_tokens.push(TriviaToken { leading, token });
+        }
+        (trivia_tokens, trivia.collect(), errors)
+    }
 
-        Span { start, end }
+    fn add_token(&mut self, token: Token) {
+        self.pending.push_back(Ok(token));
     }
 
-    fn next_line(&mut self, index: usize) {
-        self.line += 1;
-        self.start_of_line = (index + 1) as u16;
+    fn add_error(&mut self, err: BessyError) {
+        self.pending.push_back(Err(err));
     }
 
-    fn column(&mut self, start_pos: usize) -> u16 {
-        self.column = start_pos as u16 - self.start_of_line;
-        self.column
+    // characters that don't start a token are reported and skipped,
+    // so every lexical error in the text is found in a single scan
+    pub fn scan_all(&mut self) -> (Vec<Token>, BessyErrors) {
+        let mut tokens = Vec::new();
+        let mut errors = Vec::new();
+        for item in self.by_ref() {
+            match item {
+                Ok(token) => tokens.push(token),
+                Err(err) => errors.push(err),
+            }
+        }
+        (tokens, errors.into())
     }
 
-    pub fn scan(&mut self) -> Result<Vec<Token>, BessyError> {
-        while let Some(&(start_pos, c)) = self.cursor.peek() {
-            match c {
-                '(' | ')' | '.' | '-' | '+' | '*' | ';' | '{' | '}' | ','
-                | '/' | '%' => self.scan_single_token(),
-                '~' => self.scan_comment(),
-                '!' | '=' | '>' | '<' => self.scan_double_token(),
-                ' ' | '\r' | '\t' => {
+    // scans the text up to the end of the next token, trivia or error
+    // and returns false once the end of the text has been reached
+    fn scan_next(&mut self) -> bool {
+        let Some(&(start_pos, c)) = self.cursor.peek() else {
+            if let Some(&(start, _)) = self.interpolations.first() {
+                self.interpolations.clear();
+                let span = self.make_span(start, self.text.len() - start);
+                self.add_error(BessyError::UnterminatedString(span));
+                return true;
+            }
+            return false;
+        };
+        match c {
+            '(' | ')' | '.' | '-' | '+' | '*' | ';' | ',' | '%' => {
+                self.scan_single_token()
+            }
+            '/' => match self.text[start_pos + 1..].chars().next() {
+                Some('/') => self.scan_comment(start_pos),
+                Some('*') => self.scan_block_comment(start_pos),
+                _ => self.scan_single_token(),
+            },
+            '{' => {
+                if let Some((_, depth)) = self.interpolations.last_mut() {
+                    *depth += 1;
+                }
+                self.scan_single_token()
+            }
+            '}' => match self.interpolations.last_mut() {
+                Some((_, 0)) => {
+                    self.interpolations.pop();
                     self.cursor.next();
+                    self.scan_string_part(start_pos);
                 }
-                '\n' => {
-                    let (index, _) = self.cursor.next().unwrap();
-                    self.next_line(index);
+                Some((_, depth)) => {
+                    *depth -= 1;
+                    self.scan_single_token()
                 }
-                '"' => {
-                    let token = self.scan_string()?;
-                    self.tokens.push(token);
-                }
-                _ => {
-                    if c.is_ascii_digit() {
-                        self.scan_number(start_pos)
-                    } else if c.is_ascii_alphanumeric() || c == '_' {
-                        self.scan_identifier(start_pos);
-                    } else {
-                        self.cursor.next();
-                        let span = self.make_span(start_pos, c.len_utf8());
-                        self.tokens.push(Token::new(TokenType::Unknown, span));
-                    }
+                None => self.scan_single_token(),
+            },
+            #[cfg(feature = "tilde-comments")]
+            '~' => self.scan_comment(start_pos),
+            '!' | '=' | '>' | '<' => self.scan_double_token(),
+            ' ' | '\r' | '\t' => {
+                while self
+                    .cursor
+                    .next_if(|x| matches!(x.1, ' ' | '\r' | '\t'))
+                    .is_some()
+                {}
+                self.add_trivia(TriviaKind::Whitespace, start_pos);
+            }
+            '\n' => {
+                self.cursor.next();
+                self.add_trivia(TriviaKind::Newline, start_pos);
+            }
+            '"' => self.scan_string(),
+            _ => {
+                if c.is_ascii_digit() {
+                    self.scan_number(start_pos)
+                } else if is_identifier_start(c) {
+                    self.scan_identifier(start_pos);
+                } else {
+                    self.cursor.next();
+                    self.add_trivia(TriviaKind::Error, start_pos);
+                    let span = self.make_span(start_pos, c.len_utf8());
+                    self.add_error(BessyError::UnexpectedCharacter(c, span));
                 }
             }
         }
-        Ok(self.tokens.clone())
+        true
     }
 
     fn scan_single_token(&mut self) {
@@ -216,16 +427,35 @@ impl<'src> Lexer<'src> {
             _ => unreachable!(),
         };
         let span = self.make_span(start_pos, c.len_utf8());
-        self.tokens.push(Token::new(kind, span));
+        self.add_token(Token::new(kind, span));
     }
 
-    fn scan_comment(&mut self) {
-        for (index, ch) in self.cursor.by_ref() {
-            if ch == '\n' {
-                self.next_line(index);
-                break;
+    // block comments nest, so a block of code that already contains
+    // a block comment can still be commented out
+    fn scan_block_comment(&mut self, start_pos: usize) {
+        self.cursor.nth(1); // skip the opening `/*`
+        let mut depth = 1;
+        while let Some((_, ch)) = self.cursor.next() {
+            if ch == '/' && self.cursor.next_if(|x| x.1 == '*').is_some() {
+                depth += 1;
+            } else if ch == '*' && self.cursor.next_if(|x| x.1 == '/').is_some()
+            {
+                depth -= 1;
+                if depth == 0 {
+                    self.add_trivia(TriviaKind::Comment, start_pos);
+                    return;
+                }
             }
         }
+        self.add_trivia(TriviaKind::Comment, start_pos);
+        let span = self.make_span(start_pos, 2);
+        self.add_error(BessyError::UnterminatedComment(span));
+    }
+
+    // the newline that ends the comment is not part of it
+    fn scan_comment(&mut self, start_pos: usize) {
+        while self.cursor.next_if(|x| x.1 != '\n').is_some() {}
+        self.add_trivia(TriviaKind::Comment, start_pos);
     }
 
     fn check_next(
@@ -236,7 +466,7 @@ impl<'src> Lexer<'src> {
         that: TokenType,
     ) -> Token {
         if let Some((_, ch)) = self.cursor.next_if(|x| x.1 == '=') {
-            Token::new(this, self.make_span(start_pos, ch.len_utf8()))
+            Token::new(this, self.make_span(start_pos, len + ch.len_utf8()))
         } else {
             Token::new(that, self.make_span(start_pos, len))
         }
@@ -271,60 +501,180 @@ impl<'src> Lexer<'src> {
             ),
             _ => unreachable!(),
         };
-        self.tokens.push(token);
+        self.add_token(token);
     }
 
-    fn scan_string(&mut self) -> Result<Token, BessyError> {
-        let mut lexeme = String::from("");
+    fn scan_string(&mut self) {
         let (start_pos, _) = self.cursor.next().unwrap(); // skip opening quotes
-        let start = start_pos + 1;
-        while let Some((_, ch)) = self.cursor.next_if(|x| x.1 != '"') {
-            lexeme.push(ch);
+        self.scan_string_part(start_pos);
+    }
+
+    // scans a string literal up to its closing quote, or up to the
+    // start of an interpolated expression in which case the rest of
+    // the string is scanned once the matching closing brace is found,
+    // `start` is the quote or brace the part starts at
+    fn scan_string_part(&mut self, start: usize) {
+        let mut lexeme = String::new();
+        while let Some((index, ch)) = self.cursor.next() {
+            match ch {
+                '"' => {
+                    let span = self.make_span(start, index + 1 - start);
+                    let kind = TokenType::StringLiteral(lexeme);
+                    self.add_token(Token::new(kind, span));
+                    return;
+                }
+                '$' if self.cursor.next_if(|x| x.1 == '{').is_some() => {
+                    let span = self.make_span(start, index + 2 - start);
+                    let kind = TokenType::Interpolation(lexeme);
+                    self.add_token(Token::new(kind, span));
+                    self.interpolations.push((start, 0));
+                    return;
+                }
+                '\\' if self.cursor.peek().is_some() => {
+                    match self.scan_escape(index) {
+                        Ok(ch) => lexeme.push(ch),
+                        Err(err) => self.add_error(err),
+                    }
+                }
+                _ => lexeme.push(ch),
+            }
         }
-        let length = lexeme.len();
-        if self.cursor.peek().map_or(false, |x| x.1 == '"') {
-            let _ = self.cursor.next();
-            Ok(Token::new(
-                TokenType::StringLiteral(lexeme),
-                self.make_span(start, length),
-            ))
-        } else {
-            Err(BessyError::UnterminatedString(
-                self.make_span(start, length),
-            ))
+        self.add_trivia(TriviaKind::Error, start);
+        let span = self.make_span(start, self.text.len() - start);
+        self.add_error(BessyError::UnterminatedString(span));
+    }
+
+    fn scan_escape(&mut self, start: usize) -> Result<char, BessyError> {
+        let (_, ch) = self.cursor.next().unwrap();
+        let escaped = match ch {
+            'n' => Some('\n'),
+            't' => Some('\t'),
+            '"' => Some('"'),
+            '\\' => Some('\\'),
+            '$' => Some('$'),
+            'u' => self.scan_unicode_escape(),
+            _ => None,
+        };
+        escaped.ok_or_else(|| {
+            let end = self.cursor.peek().map_or(self.text.len(), |x| x.0);
+            let span = self.make_span(start, end - start);
+            BessyError::InvalidEscape(self.text[start..end].into(), span)
+        })
+    }
+
+    // `\u{...}` takes the code point of the character in hexadecimal
+    fn scan_unicode_escape(&mut self) -> Option<char> {
+        self.cursor.next_if(|x| x.1 == '{')?;
+        let mut digits = String::new();
+        while let Some((_, digit)) =
+            self.cursor.next_if(|x| x.1.is_ascii_hexdigit())
+        {
+            digits.push(digit);
+        }
+        self.cursor.next_if(|x| x.1 == '}')?;
+        if digits.is_empty() || digits.len() > 6 {
+            return None;
         }
+        char::from_u32(u32::from_str_radix(&digits, 16).ok()?)
+    }
+
+    // consumes digits that may be separated by underscores and keeps
+    // only the digits, returns false unless the run both starts and
+    // ends with a digit
+    fn scan_digits(&mut self, radix: u32, digits: &mut String) -> bool {
+        let starts_with_digit =
+            self.cursor.peek().is_some_and(|x| x.1.is_digit(radix));
+        let mut last = '_';
+        while let Some((_, ch)) =
+            self.cursor.next_if(|x| x.1.is_digit(radix) || x.1 == '_')
+        {
+            if ch != '_' {
+                digits.push(ch);
+            }
+            last = ch;
+        }
+        starts_with_digit && last != '_'
     }
 
     fn scan_number(&mut self, start_pos: usize) {
-        let mut lexeme = String::from("");
-        while let Some((_, num)) = self.cursor.next_if(|x| x.1.is_ascii_digit())
+        let rest = &self.text[start_pos..];
+        let radix = if rest.starts_with("0x") || rest.starts_with("0X") {
+            16
+        } else if rest.starts_with("0b") || rest.starts_with("0B") {
+            2
+        } else {
+            10
+        };
+
+        let mut digits = String::new();
+        let mut valid;
+        if radix == 10 {
+            valid = self.scan_digits(10, &mut digits);
+            // a dot is only part of the number when a digit follows
+            // it, so that `1.foo` is a number followed by a dot
+            if let Some(&(index, '.')) = self.cursor.peek() {
+                let next = self.text[index + 1..].chars().next();
+                if next.is_some_and(|c| c.is_ascii_digit()) {
+                    self.cursor.next();
+                    digits.push('.');
+                    valid &= self.scan_digits(10, &mut digits);
+                }
+            }
+            if self.cursor.next_if(|x| matches!(x.1, 'e' | 'E')).is_some() {
+                digits.push('e');
+                if let Some((_, sign)) =
+                    self.cursor.next_if(|x| matches!(x.1, '+' | '-'))
+                {
+                    digits.push(sign);
+                }
+                valid &= self.scan_digits(10, &mut digits);
+            }
+        } else {
+            self.cursor.nth(1); // skip the prefix
+            valid = self.scan_digits(radix, &mut digits);
+        }
+        // letters or digits right after a number make it malformed,
+        // like the `2` in `0b12` or a trailing `e` in `1e`
+        while self
+            .cursor
+            .next_if(|x| is_identifier_continue(x.1))
+            .is_some()
         {
-            lexeme.push(num);
+            valid = false;
         }
-        if self.cursor.peek().map_or(false, |x| x.1 == '.') {
-            lexeme.push('.');
-            let _ = self.cursor.next();
-            while let Some((_, num)) =
-                self.cursor.next_if(|x| x.1.is_ascii_digit())
-            {
-                lexeme.push(num);
+
+        let end = self.cursor.peek().map_or(self.text.len(), |x| x.0);
+        let span = self.make_span(start_pos, end - start_pos);
+        let num = match radix {
+            _ if !valid => None,
+            10 => digits.parse::<f64>().ok(),
+            _ => u64::from_str_radix(&digits, radix).ok().map(|n| n as f64),
+        };
+        match num {
+            Some(num) => {
+                self.add_token(Token::new(TokenType::Number(num), span))
+            }
+            None => {
+                self.add_trivia(TriviaKind::Error, start_pos);
+                self.add_error(BessyError::InvalidNumber(
+                    self.text[start_pos..end].into(),
+                    span,
+                ));
             }
         }
-        let num = lexeme.parse::<f64>().expect("Unable to parse number.");
-        let span = self.make_span(start_pos, lexeme.len());
-        self.tokens.push(Token::new(TokenType::Number(num), span));
     }
 
     fn scan_identifier(&mut self, start_pos: usize) {
         let mut lexeme = String::from("");
         while let Some((_, ch)) =
-            self.cursor.next_if(|x| x.1.is_ascii_alphanumeric())
+            self.cursor.next_if(|x| is_identifier_continue(x.1))
         {
             lexeme.push(ch);
         }
         let len = lexeme.len();
         let kind = match lexeme.as_str() {
             "and" => TokenType::And,
+            "class" => TokenType::Class,
             "else" => TokenType::Else,
             "false" => TokenType::Boolean(false),
             "for" => TokenType::For,
@@ -334,13 +684,32 @@ impl<'src> Lexer<'src> {
             "or" => TokenType::Or,
             "print" => TokenType::Print,
             "return" => TokenType::Return,
+            "super" => TokenType::Super,
+            "this" => TokenType::This,
             "true" => TokenType::Boolean(true),
             "var" => TokenType::Var,
             "while" => TokenType::While,
             _ => TokenType::Identifier(lexeme),
         };
         let span = self.make_span(start_pos, len);
-        self.tokens.push(Token::new(kind, span));
+        self.add_token(Token::new(kind, span));
+    }
+}
+
+// tokens are scanned on demand, so only as much of the text as the
+// consumer has asked for is scanned at any point
+impl Iterator for Lexer<'_> {
+    type Item = Result<Token, BessyError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(item) = self.pending.pop_front() {
+                return Some(item);
+            }
+            if !self.scan_next() {
+                return None;
+            }
+        }
     }
 }
 
@@ -349,19 +718,16 @@ mod test_lexer {
     use super::*;
 
     fn test_runner(src: &str, expected_tokens: &[TokenType]) -> bool {
-        match Lexer::new(src).scan() {
-            Ok(tokens) => {
-                let output = tokens
-                    .iter()
-                    .map(|t| t.kind.clone())
-                    .collect::<Vec<TokenType>>();
-                output.as_slice() == expected_tokens
-            }
-            Err(error) => {
-                eprintln!("{error:?}");
-                false
-            }
+        let (tokens, errors) = Lexer::new(src).scan_all();
+        if !errors.is_empty() {
+            eprintln!("{errors:?}");
+            return false;
         }
+        let output = tokens
+            .iter()
+            .map(|t| t.kind.clone())
+            .collect::<Vec<TokenType>>();
+        output.as_slice() == expected_tokens
     }
 
     #[test]
@@ -401,6 +767,40 @@ mod test_lexer {
         assert!(test_runner("1", &[TokenType::Number(1.0)]));
         assert!(test_runner("123", &[TokenType::Number(123.0)]));
         assert!(test_runner("1.00", &[TokenType::Number(1.00)]));
+        assert!(test_runner("1_000_000", &[TokenType::Number(1e6)]));
+        assert!(test_runner(
+            "0xFF 0b1010",
+            &[TokenType::Number(255.0), TokenType::Number(10.0)]
+        ));
+        assert!(test_runner(
+            "1.5e-3 2E+2 3e1",
+            &[
+                TokenType::Number(1.5e-3),
+                TokenType::Number(200.0),
+                TokenType::Number(30.0)
+            ]
+        ));
+        assert!(test_runner(
+            "1.foo",
+            &[
+                TokenType::Number(1.0),
+                TokenType::Dot,
+                TokenType::Identifier("foo".into())
+            ]
+        ));
+    }
+
+    #[test]
+    fn test_invalid_numbers() {
+        for src in ["1_", "1__2_", "0x", "0b102", "1e", "1.5e+", "12ab", "1_.5"]
+        {
+            let (tokens, errors) = Lexer::new(src).scan_all();
+            assert!(tokens.is_empty(), "{src}");
+            assert!(matches!(
+                &*errors,
+                [BessyError::InvalidNumber(lexeme, _)] if **lexeme == *src
+            ));
+        }
     }
 
     #[test]
@@ -422,4 +822,209 @@ mod test_lexer {
             &[TokenType::Identifier("human".into())]
         ));
     }
+
+    #[test]
+    fn test_errors() {
+        let (tokens, errors) = Lexer::new("@ 1 # 2 \"three").scan_all();
+        let output = tokens.into_iter().map(|t| t.kind).collect::<Vec<_>>();
+        assert_eq!(output, [TokenType::Number(1.0), TokenType::Number(2.0)]);
+        assert!(matches!(
+            &*errors,
+            [
+                BessyError::UnexpectedCharacter('@', _),
+                BessyError::UnexpectedCharacter('#', _),
+                BessyError::UnterminatedString(_),
+            ]
+        ));
+    }
+
+    #[test]
+    fn test_spans() {
+        let (tokens, _) = Lexer::new("var a =\n  \"multi\nline\";").scan_all();
+        let string = &tokens[3].span;
+        assert_eq!(
+            (string.start.line_number, string.start.column_number),
+            (2, 2)
+        );
+        assert_eq!((string.end.line_number, string.end.column_number), (3, 5));
+        assert_eq!((string.start.offset, string.end.offset), (10, 22));
+        let index = LineIndex::new("one\ntwo");
+        assert_eq!(index.offset(2, 1), Some(5));
+        assert_eq!(index.offset(3, 0), None);
+        assert_eq!(index.position(5).line_number, 2);
+        // positions asked for out of order are counted from scratch
+        let index = LineIndex::new("ä ö\nü");
+        assert_eq!(index.position(5).column_number, 3);
+        assert_eq!(index.position(3).column_number, 2);
+        assert_eq!(index.position(6).column_number, 0);
+        assert_eq!(index.position(2).column_number, 1);
+        assert_eq!(index.position(8).column_number, 1);
+    }
+
+    #[test]
+    fn test_underscores() {
+        assert!(test_runner(
+            "_private snake_case x_1",
+            &[
+                TokenType::Identifier("_private".into()),
+                TokenType::Identifier("snake_case".into()),
+                TokenType::Identifier("x_1".into()),
+            ]
+        ));
+    }
+
+    #[test]
+    fn test_columns() {
+        let source = "print \"héllo 😀\"; name";
+        let (tokens, _) = Lexer::new(source).scan_all();
+        assert_eq!(tokens[3].span.start.column_number, 17);
+        let index = LineIndex::new(source);
+        assert_eq!(index.utf16_column(tokens[3].span.start.offset), 18);
+        assert_eq!(index.offset(1, 17), Some(tokens[3].span.start.offset));
+    }
+
+    #[cfg(feature = "unicode-xid")]
+    #[test]
+    fn test_unicode_identifiers() {
+        assert!(test_runner(
+            "café _über 変数",
+            &[
+                TokenType::Identifier("café".into()),
+                TokenType::Identifier("_über".into()),
+                TokenType::Identifier("変数".into()),
+            ]
+        ));
+    }
+
+    #[cfg(not(feature = "unicode-xid"))]
+    #[test]
+    fn test_unicode_identifiers() {
+        let (_, errors) = Lexer::new("café").scan_all();
+        assert!(matches!(
+            &*errors,
+            [BessyError::UnexpectedCharacter('é', _)]
+        ));
+    }
+
+    #[test]
+    fn test_escapes() {
+        assert!(test_runner(
+            r#""a\tb\n\"c\" \\ \$ \u{e9}\u{1F600}""#,
+            &[TokenType::StringLiteral("a\tb\n\"c\" \\ $ é😀".into())]
+        ));
+        let (tokens, errors) = Lexer::new(r#""a\qb\u{110000}c""#).scan_all();
+        assert_eq!(tokens[0].kind, TokenType::StringLiteral("abc".into()));
+        assert!(matches!(
+            &*errors,
+            [
+                BessyError::InvalidEscape(q, _),
+                BessyError::InvalidEscape(u, _),
+            ] if &**q == "\\q" && &**u == "\\u{110000}"
+        ));
+    }
+
+    #[test]
+    fn test_interpolation() {
+        assert!(test_runner(
+            r#""a ${b} c ${ {} } d""#,
+            &[
+                TokenType::Interpolation("a ".into()),
+                TokenType::Identifier("b".into()),
+                TokenType::Interpolation(" c ".into()),
+                TokenType::LeftBrace,
+                TokenType::RightBrace,
+                TokenType::StringLiteral(" d".into()),
+            ]
+        ));
+        assert!(test_runner(
+            r#""${"${x}"}""#,
+            &[
+                TokenType::Interpolation("".into()),
+                TokenType::Interpolation("".into()),
+                TokenType::Identifier("x".into()),
+                TokenType::StringLiteral("".into()),
+                TokenType::StringLiteral("".into()),
+            ]
+        ));
+        let (_, errors) = Lexer::new(r#""a ${b"#).scan_all();
+        assert!(matches!(&*errors, [BessyError::UnterminatedString(_)]));
+    }
+
+    #[test]
+    fn test_comments() {
+        assert!(test_runner(
+            "1 // one\n/* two /* nested */\n*/ 3 / 4",
+            &[
+                TokenType::Number(1.0),
+                TokenType::Number(3.0),
+                TokenType::Slash,
+                TokenType::Number(4.0)
+            ]
+        ));
+        let (tokens, errors) = Lexer::new("1 /* /* */").scan_all();
+        assert_eq!(tokens.len(), 1);
+        assert!(matches!(&*errors, [BessyError::UnterminatedComment(_)]));
+    }
+
+    #[cfg(feature = "tilde-comments")]
+    #[test]
+    fn test_tilde_comments() {
+        assert!(test_runner(
+            "1 ~ one\n2",
+            &[TokenType::Number(1.0), TokenType::Number(2.0)]
+        ));
+    }
+
+    #[test]
+    fn test_trivia() {
+        let src = "var a = \"x ${ b }\";  // c\r\n/* d */ print a\n@ 0x \"f";
+        let (tokens, trailing, errors) = Lexer::new(src).scan_with_trivia();
+        let mut text = String::new();
+        for token in &tokens {
+            for trivia in &token.leading {
+                text += &src[trivia.span.start.offset..trivia.span.end.offset];
+            }
+            text += &src
+                [token.token.span.start.offset..token.token.span.end.offset];
+        }
+        for trivia in &trailing {
+            text += &src[trivia.span.start.offset..trivia.span.end.offset];
+        }
+        assert_eq!(text, src);
+        assert_eq!(errors.len(), 3);
+        let kinds = |trivia: &[Trivia]| {
+            trivia.iter().map(|t| t.kind).collect::<Vec<_>>()
+        };
+        assert_eq!(
+            kinds(&tokens[7].leading),
+            [
+                TriviaKind::Whitespace,
+                TriviaKind::Comment,
+                TriviaKind::Newline,
+                TriviaKind::Comment,
+                TriviaKind::Whitespace,
+            ]
+        );
+        assert_eq!(tokens[7].token.kind, TokenType::Print);
+        assert_eq!(kinds(&trailing).last(), Some(&TriviaKind::Error));
+    }
+
+    #[test]
+    fn test_iterator() {
+        let mut lexer = Lexer::new("1 @ 2");
+        assert!(matches!(
+            lexer.next(),
+            Some(Ok(Token {
+                kind: TokenType::Number(_),
+                ..
+            }))
+        ));
+        assert!(matches!(
+            lexer.next(),
+            Some(Err(BessyError::UnexpectedCharacter('@', _)))
+        ));
+        assert_eq!(lexer.next().unwrap().unwrap().kind, TokenType::Number(2.0));
+        assert!(lexer.next().is_none());
+        assert!(lexer.next().is_none());
+    }
 }
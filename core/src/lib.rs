@@ -1,17 +1,105 @@
+mod chunk;
+mod compiler;
+mod debug;
 mod error;
 mod expr;
+mod heap;
+mod interpreter;
 mod lexer;
+mod object;
 mod parser;
+mod resolver;
 mod stmt;
+mod value;
+mod vm;
 
-use crate::error::BessyError;
+use std::collections::HashSet;
+use std::io::Write;
 
-pub fn evaluate(text: &str) -> Result<(), BessyError> {
-    let mut lex = lexer::Lexer::new(text);
-    let tokens = lex.scan()?;
-    println!("Tokens: {tokens:?}");
-    let mut parser = parser::Parser::new(tokens.into_iter());
-    let ast = parser.parse()?;
-    println!("AST: {ast:?}");
-    Ok(())
+pub use crate::error::{
+    BessyError, BessyErrors, Diagnostic, Label, TraceFrame,
+};
+pub use crate::expr::Expr;
+pub use crate::interpreter::{Interpreter, Program};
+pub use crate::lexer::{
+    Lexer, LineIndex, Position, Span, Token, TokenType, Trivia, TriviaKind,
+    TriviaToken,
+};
+pub use crate::stmt::Stmt;
+pub use crate::value::HostValue;
+pub use crate::vm::Options;
+
+// parses as much of the program as it can, the tree that is returned
+// is missing the statements that have errors in them, which makes it
+// of use to tools that work on programs as they are being written
+pub fn parse(text: &str) -> (Vec<Stmt>, BessyErrors) {
+    // the parser pulls tokens from the lexer as it needs them and the
+    // lexical errors are set aside along the way, the text of an error
+    // is passed on as an invalid token so that the parser can recover
+    // from it without reporting it a second time
+    let mut errors = Vec::new();
+    let mut invalid = HashSet::new();
+    let tokens = lexer::Lexer::new(text).filter_map(|token| match token {
+        Ok(token) => Some(token),
+        Err(err) => {
+            let span = err.diagnostic().span;
+            errors.push(err);
+            span.map(|span| {
+                invalid.insert(span);
+                lexer::Token::new(lexer::TokenType::Invalid, span)
+            })
+        }
+    });
+    let (ast, parse_errors) = parser::Parser::new(tokens).parse();
+    errors.extend(parse_errors.into_iter().filter(|err| {
+        !matches!(
+            err,
+            BessyError::Unexpected { span: Some(span), .. }
+                if invalid.contains(span)
+        )
+    }));
+    (ast, errors.into())
+}
+
+fn parse_program(text: &str) -> Result<Vec<Stmt>, BessyErrors> {
+    let (ast, errors) = parse(text);
+    if errors.is_empty() {
+        Ok(ast)
+    } else {
+        Err(errors)
+    }
+}
+
+// runs the program writing what it prints to `output` and every
+// error it runs into, rendered with the source lines it points at, to
+// `diagnostics`, the errors are returned as well
+pub fn evaluate(
+    text: &str,
+    output: &mut impl Write,
+    diagnostics: &mut impl Write,
+) -> Result<(), BessyErrors> {
+    evaluate_with_options(text, output, diagnostics, Options::default())
+}
+
+pub fn evaluate_with_options(
+    text: &str,
+    output: &mut impl Write,
+    diagnostics: &mut impl Write,
+    options: Options,
+) -> Result<(), BessyErrors> {
+    let result = parse_program(text).and_then(|ast| {
+        Ok(vm::Vm::with_options(options).interpret(&ast, output)?)
+    });
+    if let Err(errors) = &result {
+        // there is nowhere left to report a failure to write
+        let _ = diagnostics.write_all(errors.render(text).as_bytes());
+    }
+    result
+}
+
+// compiles the program without running it and returns a listing of
+// the bytecode of the script and of every function declared in it
+pub fn disassemble(text: &str) -> Result<String, BessyErrors> {
+    let ast = parse_program(text)?;
+    Ok(vm::Vm::new().disassemble(&ast)?)
 }
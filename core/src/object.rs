@@ -0,0 +1,98 @@
+use crate::chunk::Chunk;
+use crate::heap::ObjRef;
+use crate::value::{HostValue, Value};
+use std::collections::HashMap;
+use std::rc::Rc;
+
+// natives are given the arguments as the host sees them and an error
+// they return becomes a runtime error at the call
+pub type NativeFn = Rc<dyn Fn(&[HostValue]) -> Result<HostValue, String>>;
+
+#[derive(Debug)]
+pub enum Obj {
+    String(ObjString),
+    Function(ObjFunction),
+    Closure(ObjClosure),
+    Native(ObjNative),
+    Class(ObjClass),
+    Instance(ObjInstance),
+    BoundMethod(ObjBoundMethod),
+    Upvalue(ObjUpvalue),
+}
+
+#[derive(Debug)]
+pub struct ObjString {
+    pub value: Rc<str>,
+}
+
+#[derive(Debug, Default)]
+pub struct ObjFunction {
+    pub arity: usize,
+    pub chunk: Chunk,
+    pub name: Option<ObjRef>,
+    pub upvalues: Vec<Upvalue>,
+}
+
+// tells a new closure where to find a variable it captures, either
+// in a stack slot of the enclosing function or in one of its upvalues
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Upvalue {
+    pub index: u8,
+    pub is_local: bool,
+}
+
+// every function is wrapped in a closure at runtime so that the
+// virtual machine only ever has to call one kind of object
+#[derive(Debug)]
+pub struct ObjClosure {
+    pub function: ObjRef,
+    pub upvalues: Vec<ObjRef>,
+    // the class a method was declared in, which is where `super`
+    // starts looking for methods
+    pub class: Option<ObjRef>,
+}
+
+#[derive(Clone)]
+pub struct ObjNative {
+    pub name: ObjRef,
+    pub arity: usize,
+    pub function: NativeFn,
+}
+
+impl std::fmt::Debug for ObjNative {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("ObjNative")
+            .field("name", &self.name)
+            .field("arity", &self.arity)
+            .finish()
+    }
+}
+
+#[derive(Debug)]
+pub struct ObjClass {
+    pub name: ObjRef,
+    pub superclass: Option<ObjRef>,
+    pub methods: HashMap<ObjRef, ObjRef>,
+}
+
+#[derive(Debug)]
+pub struct ObjInstance {
+    pub class: ObjRef,
+    pub fields: HashMap<ObjRef, Value>,
+}
+
+// a method that has been accessed on an instance remembers the
+// instance so that `this` refers to it once the method is called
+#[derive(Debug)]
+pub struct ObjBoundMethod {
+    pub receiver: Value,
+    pub method: ObjRef,
+}
+
+// a captured variable stays on the stack while the function that
+// declared it is running and is moved into the upvalue afterwards
+#[derive(Debug)]
+pub enum ObjUpvalue {
+    Open(usize),
+    Closed(Value),
+}
@@ -1,6 +1,6 @@
 // a parser takens in a stream of tokens and turns them into a
 // intermediate representation in the form of an abstract syntax tree
-use crate::error::BessyError;
+use crate::error::{BessyError, Label};
 use crate::expr::Expr;
 use crate::lexer::{Span, Token, TokenType};
 use crate::stmt::Stmt;
@@ -17,6 +17,7 @@ macro_rules! next_eq {
 pub struct Parser<T: Iterator<Item = Token>> {
     cursor: Peekable<T>,
     statements: Vec<Stmt>,
+    errors: Vec<BessyError>,
 }
 
 impl<T: Iterator<Item = Token>> Parser<T> {
@@ -24,21 +25,55 @@ impl<T: Iterator<Item = Token>> Parser<T> {
         Parser {
             statements: Vec::with_capacity(tokens.size_hint().0),
             cursor: tokens.peekable(),
+            errors: Vec::new(),
         }
     }
 
-    pub fn parse(&mut self) -> Result<Vec<Stmt>, BessyError> {
+    // a statement with a syntax error is left out of the tree and
+    // parsing resumes at the next one, the block or function it is in
+    // is kept with the rest of its statements unless the error is in
+    // the braces or the header around them
+    pub fn parse(&mut self) -> (Vec<Stmt>, Vec<BessyError>) {
         while self.cursor.peek().is_some() {
-            let stmt = self.declaration()?;
-            self.statements.push(stmt);
+            if let Some(stmt) = self.declaration() {
+                self.statements.push(stmt);
+            }
+        }
+        (
+            std::mem::take(&mut self.statements),
+            std::mem::take(&mut self.errors),
+        )
+    }
+
+    // skips tokens until the end of the current statement or the
+    // start of the next one, to avoid reporting errors that are only
+    // caused by an earlier one
+    fn synchronize(&mut self) {
+        while let Some(token) = self.cursor.peek() {
+            match token.kind {
+                TokenType::Class
+                | TokenType::Fun
+                | TokenType::Var
+                | TokenType::For
+                | TokenType::If
+                | TokenType::While
+                | TokenType::Print
+                | TokenType::Return => return,
+                TokenType::Semicolon => {
+                    self.cursor.next();
+                    return;
+                }
+                _ => {
+                    self.cursor.next();
+                }
+            }
         }
-        Ok(self.statements.clone())
     }
 
     fn peek_check(&mut self, expected: TokenType) -> bool {
         self.cursor
             .peek()
-            .map_or(false, |token| token.kind == expected)
+            .is_some_and(|token| token.kind == expected)
     }
 
     fn next_eq(&mut self, expected: TokenType) -> bool {
@@ -72,6 +107,7 @@ impl<T: Iterator<Item = Token>> Parser<T> {
         BessyError::Unexpected {
             msg: message.into(),
             span: self.cursor.peek().map(|t| t.span),
+            labels: Vec::new(),
         }
     }
 
@@ -79,16 +115,43 @@ impl<T: Iterator<Item = Token>> Parser<T> {
         BessyError::Unexpected {
             msg: message.into(),
             span: Some(span),
+            labels: Vec::new(),
         }
     }
 
-    fn declaration(&mut self) -> Result<Stmt, BessyError> {
-        if self.next_eq(TokenType::Var) {
+    // like `consume` but the error also points at the token that
+    // `expected` would have closed
+    fn consume_closing(
+        &mut self,
+        expected: TokenType,
+        error_msg: &str,
+        opening: Label,
+    ) -> Result<Token, BessyError> {
+        self.consume(expected, error_msg).map_err(|mut err| {
+            if let BessyError::Unexpected { labels, .. } = &mut err {
+                labels.push(opening);
+            }
+            err
+        })
+    }
+
+    fn declaration(&mut self) -> Option<Stmt> {
+        let stmt = if self.next_eq(TokenType::Var) {
             self.variable_declaration()
         } else if self.next_eq(TokenType::Fun) {
             self.function()
+        } else if self.next_eq(TokenType::Class) {
+            self.class_declaration()
         } else {
             self.statement()
+        };
+        match stmt {
+            Ok(stmt) => Some(stmt),
+            Err(err) => {
+                self.errors.push(err);
+                self.synchronize();
+                None
+            }
         }
     }
 
@@ -107,6 +170,33 @@ impl<T: Iterator<Item = Token>> Parser<T> {
         }
     }
 
+    fn class_declaration(&mut self) -> Result<Stmt, BessyError> {
+        let name = self.consume_identifier("Expect class name.")?;
+        let mut superclass = None;
+        if self.next_eq(TokenType::Less) {
+            let name = self.consume_identifier("Expect superclass name.")?;
+            superclass = Some(Expr::Variable(name));
+        }
+        let brace = self
+            .consume(TokenType::LeftBrace, "Expect '{' before class body.")?;
+        let mut methods = Vec::new();
+        while self.cursor.peek().is_some()
+            && !self.peek_check(TokenType::RightBrace)
+        {
+            methods.push(self.function()?);
+        }
+        self.consume_closing(
+            TokenType::RightBrace,
+            "Expect '}' after class body.",
+            Label::new(brace.span, "class body opened here"),
+        )?;
+        Ok(Stmt::Class {
+            name,
+            superclass,
+            methods,
+        })
+    }
+
     fn function(&mut self) -> Result<Stmt, BessyError> {
         let name = self.consume_identifier("Expect function name.")?;
         self.consume(TokenType::LeftParen, "Expect '(' after function name.")?;
@@ -128,16 +218,17 @@ impl<T: Iterator<Item = Token>> Parser<T> {
         }
         self.consume(TokenType::RightParen, "Expect ')' after parameters.")?;
 
-        self.consume(TokenType::LeftBrace, "Expect '{' before function body")?;
-        let body = self.block_stmt()?;
+        let brace = self
+            .consume(TokenType::LeftBrace, "Expect '{' before function body")?;
+        let body = self.block_stmt(brace.span)?;
         Ok(Stmt::Function { name, params, body })
     }
 
     fn statement(&mut self) -> Result<Stmt, BessyError> {
-        if self.next_eq(TokenType::Print) {
-            self.print_stmt()
-        } else if self.next_eq(TokenType::LeftBrace) {
-            let stmts = self.block_stmt()?;
+        if let Some(keyword) = next_eq!(self, TokenType::Print) {
+            self.print_stmt(keyword)
+        } else if let Some(brace) = next_eq!(self, TokenType::LeftBrace) {
+            let stmts = self.block_stmt(brace.span)?;
             Ok(Stmt::Block(stmts))
         } else if self.next_eq(TokenType::If) {
             self.if_stmt()
@@ -177,20 +268,27 @@ impl<T: Iterator<Item = Token>> Parser<T> {
         if !self.peek_check(TokenType::Semicolon) {
             condition = Some(self.expression()?);
         }
-        self.consume(TokenType::Semicolon, "Expect ';' after loop condition.")?;
+        let semicolon = self.consume(
+            TokenType::Semicolon,
+            "Expect ';' after loop condition.",
+        )?;
 
         let mut increment = None;
         if !self.peek_check(TokenType::RightParen) {
             increment = Some(self.expression()?);
         }
-        self.consume(TokenType::RightParen, "Expect ')' after for clauses.")?;
+        let paren = self
+            .consume(TokenType::RightParen, "Expect ')' after for clauses.")?;
         let mut body = self.statement()?;
         if let Some(increment_expression) = increment {
-            let increment_stmt = Stmt::Expression(increment_expression);
+            let increment_stmt = Stmt::Expression {
+                expr: increment_expression,
+                semicolon: paren,
+            };
             body = Stmt::Block(vec![body, increment_stmt]);
         }
         body = Stmt::While {
-            condition: condition.unwrap_or(Expr::Boolean(true)),
+            condition: condition.unwrap_or(Expr::Boolean(true, semicolon.span)),
             body: Box::new(body),
         };
         if let Some(init_statement) = init {
@@ -226,25 +324,34 @@ impl<T: Iterator<Item = Token>> Parser<T> {
         })
     }
 
-    fn block_stmt(&mut self) -> Result<Vec<Stmt>, BessyError> {
+    fn block_stmt(&mut self, brace: Span) -> Result<Vec<Stmt>, BessyError> {
         let mut stmts = Vec::new();
-        while !self.peek_check(TokenType::RightParen) {
-            stmts.push(self.declaration()?);
+        while self.cursor.peek().is_some()
+            && !self.peek_check(TokenType::RightBrace)
+        {
+            if let Some(stmt) = self.declaration() {
+                stmts.push(stmt);
+            }
         }
-        self.consume(TokenType::RightBrace, "Expect '}' after block.")?;
+        self.consume_closing(
+            TokenType::RightBrace,
+            "Expect '}' after block.",
+            Label::new(brace, "block opened here"),
+        )?;
         Ok(stmts)
     }
 
-    fn print_stmt(&mut self) -> Result<Stmt, BessyError> {
-        let expr = self.expression()?;
+    fn print_stmt(&mut self, keyword: Token) -> Result<Stmt, BessyError> {
+        let value = self.expression()?;
         self.consume(TokenType::Semicolon, "Expect semicolon.")?;
-        Ok(Stmt::Print(expr))
+        Ok(Stmt::Print { keyword, value })
     }
 
     fn expression_stmt(&mut self) -> Result<Stmt, BessyError> {
         let expr = self.expression()?;
-        self.consume(TokenType::Semicolon, "Expect semicolon.")?;
-        Ok(Stmt::Expression(expr))
+        let semicolon =
+            self.consume(TokenType::Semicolon, "Expect semicolon.")?;
+        Ok(Stmt::Expression { expr, semicolon })
     }
 
     fn expression(&mut self) -> Result<Expr, BessyError> {
@@ -252,7 +359,7 @@ impl<T: Iterator<Item = Token>> Parser<T> {
     }
 
     fn assignment(&mut self) -> Result<Expr, BessyError> {
-        let expr = self.equality()?;
+        let expr = self.logic_or()?;
         if let Some(equals) = next_eq!(self, TokenType::Equal) {
             let value = self.assignment()?;
             match expr {
@@ -260,6 +367,11 @@ impl<T: Iterator<Item = Token>> Parser<T> {
                     name: variable_name,
                     value: Box::new(value),
                 }),
+                Expr::Get { object, name } => Ok(Expr::Set {
+                    object,
+                    name,
+                    value: Box::new(value),
+                }),
                 _ => Err(self.error_with_span(
                     "Invalid assignment target.",
                     equals.span,
@@ -270,6 +382,32 @@ impl<T: Iterator<Item = Token>> Parser<T> {
         }
     }
 
+    fn logic_or(&mut self) -> Result<Expr, BessyError> {
+        let mut expr = self.logic_and()?;
+        while let Some(oper) = next_eq!(self, TokenType::Or) {
+            let right = self.logic_and()?;
+            expr = Expr::Logical {
+                left: Box::new(expr),
+                oper,
+                right: Box::new(right),
+            };
+        }
+        Ok(expr)
+    }
+
+    fn logic_and(&mut self) -> Result<Expr, BessyError> {
+        let mut expr = self.equality()?;
+        while let Some(oper) = next_eq!(self, TokenType::And) {
+            let right = self.equality()?;
+            expr = Expr::Logical {
+                left: Box::new(expr),
+                oper,
+                right: Box::new(right),
+            };
+        }
+        Ok(expr)
+    }
+
     fn equality(&mut self) -> Result<Expr, BessyError> {
         let mut expr = self.comparison()?;
         while let Some(oper) =
@@ -350,6 +488,13 @@ impl<T: Iterator<Item = Token>> Parser<T> {
         loop {
             if self.next_eq(TokenType::LeftParen) {
                 expr = self.finish_call(expr)?;
+            } else if self.next_eq(TokenType::Dot) {
+                let name =
+                    self.consume_identifier("Expect property name after '.'.")?;
+                expr = Expr::Get {
+                    object: Box::new(expr),
+                    name,
+                };
             } else {
                 break;
             }
@@ -380,15 +525,66 @@ impl<T: Iterator<Item = Token>> Parser<T> {
         })
     }
 
+    // an interpolated string is lowered into the concatenation of
+    // its parts, "a ${b} c" is parsed as "a " + b + " c" where b is
+    // turned into a string first
+    fn interpolation(
+        &mut self,
+        head: String,
+        span: Span,
+    ) -> Result<Expr, BessyError> {
+        let concat = |left: Expr, right: Expr, span: Span| Expr::Binary {
+            left: Box::new(left),
+            oper: Token::new(TokenType::Plus, span),
+            right: Box::new(right),
+        };
+        let mut expr = Expr::String(head, span);
+        let mut span = span;
+        loop {
+            let value = Expr::Stringify(Box::new(self.expression()?));
+            expr = concat(expr, value, span);
+            let Some(token) = self.cursor.next_if(|t| {
+                matches!(
+                    t.kind,
+                    TokenType::Interpolation(_) | TokenType::StringLiteral(_)
+                )
+            }) else {
+                return Err(self.error("Expect '}' after interpolation."));
+            };
+            span = token.span;
+            match token.kind {
+                TokenType::Interpolation(lexeme) => {
+                    if !lexeme.is_empty() {
+                        expr = concat(expr, Expr::String(lexeme, span), span);
+                    }
+                }
+                TokenType::StringLiteral(lexeme) => {
+                    if !lexeme.is_empty() {
+                        expr = concat(expr, Expr::String(lexeme, span), span);
+                    }
+                    return Ok(expr);
+                }
+                _ => unreachable!(),
+            }
+        }
+    }
+
     // FIXME: Try not move .next() when expr.kind is not of
     // the expected expression type
     fn primary(&mut self) -> Result<Expr, BessyError> {
         if let Some(expr) = self.cursor.next() {
             match expr.kind {
-                TokenType::Nil => Ok(Expr::Nil),
-                TokenType::Boolean(value) => Ok(Expr::Boolean(value)),
-                TokenType::Number(num) => Ok(Expr::Number(num)),
-                TokenType::StringLiteral(lexeme) => Ok(Expr::String(lexeme)),
+                TokenType::Nil => Ok(Expr::Nil(expr.span)),
+                TokenType::Boolean(value) => {
+                    Ok(Expr::Boolean(value, expr.span))
+                }
+                TokenType::Number(num) => Ok(Expr::Number(num, expr.span)),
+                TokenType::StringLiteral(lexeme) => {
+                    Ok(Expr::String(lexeme, expr.span))
+                }
+                TokenType::Interpolation(lexeme) => {
+                    self.interpolation(lexeme, expr.span)
+                }
                 TokenType::LeftParen => {
                     let expr = self.expression()?;
                     self.consume(
@@ -398,6 +594,16 @@ impl<T: Iterator<Item = Token>> Parser<T> {
                     Ok(Expr::Group(Box::new(expr)))
                 }
                 TokenType::Identifier(_) => Ok(Expr::Variable(expr)),
+                TokenType::This => Ok(Expr::This(expr)),
+                TokenType::Super => {
+                    self.consume(TokenType::Dot, "Expect '.' after 'super'.")?;
+                    let method = self
+                        .consume_identifier("Expect superclass method name.")?;
+                    Ok(Expr::Super {
+                        keyword: expr,
+                        method,
+                    })
+                }
                 other => Err(self.error_with_span(
                     format!(
                         "Expected a primary expression but found `{other}`"
@@ -411,3 +617,52 @@ impl<T: Iterator<Item = Token>> Parser<T> {
         }
     }
 }
+
+#[cfg(test)]
+mod test_parser {
+    use crate::lexer::Lexer;
+
+    use super::*;
+
+    // returns the number of top level statements and errors
+    fn test_runner(src: &str) -> (usize, usize) {
+        let (tokens, _) = Lexer::new(src).scan_all();
+        let (stmts, errors) = Parser::new(tokens.into_iter()).parse();
+        (stmts.len(), errors.len())
+    }
+
+    #[test]
+    fn test_valid() {
+        assert_eq!(test_runner("var a = 1; print a;"), (2, 0));
+        assert_eq!(test_runner("class A < B { f() { return 1; } }"), (1, 0));
+    }
+
+    #[test]
+    fn test_recovery() {
+        assert_eq!(test_runner("var = 1; print 2; var 3;"), (1, 2));
+        assert_eq!(test_runner("print 1 var a = 2; print a;"), (2, 1));
+        assert_eq!(test_runner("print (1; print 2 print 3;"), (1, 2));
+        assert_eq!(test_runner("fun f() { print ; print 1; } f();"), (2, 1));
+        assert_eq!(test_runner("{ var a = 1;"), (0, 1));
+    }
+
+    #[test]
+    fn test_partial_tree() {
+        let (stmts, errors) =
+            crate::parse("fun f() { print ; print 1; } print f(;");
+        assert_eq!(errors.len(), 2);
+        let [Stmt::Function { body, .. }] = stmts.as_slice() else {
+            panic!("expected only the function, found {stmts:?}");
+        };
+        assert!(matches!(body.as_slice(), [Stmt::Print { .. }]));
+    }
+
+    #[test]
+    fn test_lexical_errors() {
+        let error_count = |src| crate::parse(src).1.len();
+        assert_eq!(error_count("print @;"), 1);
+        assert_eq!(error_count("print 1 @;"), 1);
+        assert_eq!(error_count("var a = \"one;"), 1);
+        assert_eq!(error_count("print @; print ;"), 2);
+    }
+}
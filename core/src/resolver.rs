@@ -0,0 +1,306 @@
+// the resolver walks the abstract syntax tree before it is compiled
+// to catch mistakes that can be found without running the program,
+// and works out how many scopes lie between every use of a local
+// variable and the scope that declares it
+use crate::compiler::identifier;
+use crate::error::BessyError;
+use crate::expr::Expr;
+use crate::lexer::{Span, Token};
+use crate::stmt::Stmt;
+use std::collections::HashMap;
+
+#[derive(Clone, Copy, PartialEq)]
+enum FunctionKind {
+    None,
+    Function,
+    Method,
+    Initializer,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum ClassKind {
+    None,
+    Class,
+    Subclass,
+}
+
+struct Resolver {
+    // a name maps to false while its initializer is being resolved
+    // and to true once the variable can be used
+    scopes: Vec<HashMap<String, bool>>,
+    function: FunctionKind,
+    class: ClassKind,
+    locals: HashMap<Span, usize>,
+}
+
+// returns the scope depth of every local variable that is read or
+// assigned, keyed by the span of its name, a name that is missing
+// from the table refers to a global variable
+pub fn resolve(stmts: &[Stmt]) -> Result<HashMap<Span, usize>, BessyError> {
+    let mut resolver = Resolver {
+        scopes: Vec::new(),
+        function: FunctionKind::None,
+        class: ClassKind::None,
+        locals: HashMap::new(),
+    };
+    resolver.statements(stmts)?;
+    Ok(resolver.locals)
+}
+
+fn error(message: &str, span: Span) -> BessyError {
+    BessyError::Compile {
+        msg: message.into(),
+        span,
+    }
+}
+
+impl Resolver {
+    fn begin_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    fn end_scope(&mut self) {
+        self.scopes.pop();
+    }
+
+    fn declare(&mut self, name: &Token) -> Result<(), BessyError> {
+        let Some(scope) = self.scopes.last_mut() else {
+            return Ok(());
+        };
+        let name_str = identifier(name);
+        if scope.contains_key(name_str) {
+            return Err(error(
+                "Already a variable with this name in this scope.",
+                name.span,
+            ));
+        }
+        scope.insert(name_str.to_string(), false);
+        Ok(())
+    }
+
+    fn define(&mut self, name: &Token) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(identifier(name).to_string(), true);
+        }
+    }
+
+    fn resolve_local(&mut self, name: &Token) {
+        let name_str = identifier(name);
+        if let Some(depth) = self
+            .scopes
+            .iter()
+            .rev()
+            .position(|scope| scope.contains_key(name_str))
+        {
+            self.locals.insert(name.span, depth);
+        }
+    }
+
+    fn statements(&mut self, stmts: &[Stmt]) -> Result<(), BessyError> {
+        for stmt in stmts {
+            self.statement(stmt)?;
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self, stmt: &Stmt) -> Result<(), BessyError> {
+        match stmt {
+            Stmt::Var { name, init } => {
+                self.declare(name)?;
+                if let Some(init) = init {
+                    self.expression(init)?;
+                }
+                self.define(name);
+                Ok(())
+            }
+            Stmt::Print { value: expr, .. } | Stmt::Expression { expr, .. } => {
+                self.expression(expr)
+            }
+            Stmt::Block(stmts) => {
+                self.begin_scope();
+                self.statements(stmts)?;
+                self.end_scope();
+                Ok(())
+            }
+            Stmt::If {
+                condition,
+                then,
+                elze,
+            } => {
+                self.expression(condition)?;
+                self.statement(then)?;
+                if let Some(elze) = elze {
+                    self.statement(elze)?;
+                }
+                Ok(())
+            }
+            Stmt::While { condition, body } => {
+                self.expression(condition)?;
+                self.statement(body)
+            }
+            Stmt::Function { name, params, body } => {
+                // defined before its body is resolved so that the
+                // function can call itself
+                self.declare(name)?;
+                self.define(name);
+                self.function(FunctionKind::Function, params, body)
+            }
+            Stmt::Class {
+                name,
+                superclass,
+                methods,
+            } => self.class(name, superclass.as_ref(), methods),
+            Stmt::Return { keyword, value } => {
+                if self.function == FunctionKind::None {
+                    return Err(error(
+                        "Can't return from top-level code.",
+                        keyword.span,
+                    ));
+                }
+                if let Some(value) = value {
+                    if self.function == FunctionKind::Initializer {
+                        return Err(error(
+                            "Can't return a value from an initializer.",
+                            keyword.span,
+                        ));
+                    }
+                    self.expression(value)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn function(
+        &mut self,
+        kind: FunctionKind,
+        params: &[Token],
+        body: &[Stmt],
+    ) -> Result<(), BessyError> {
+        let enclosing = self.function;
+        self.function = kind;
+        self.begin_scope();
+        for param in params {
+            self.declare(param)?;
+            self.define(param);
+        }
+        self.statements(body)?;
+        self.end_scope();
+        self.function = enclosing;
+        Ok(())
+    }
+
+    fn class(
+        &mut self,
+        name: &Token,
+        superclass: Option<&Expr>,
+        methods: &[Stmt],
+    ) -> Result<(), BessyError> {
+        let enclosing = self.class;
+        self.class = ClassKind::Class;
+        self.declare(name)?;
+        self.define(name);
+
+        if let Some(superclass) = superclass {
+            let Expr::Variable(superclass_name) = superclass else {
+                unreachable!()
+            };
+            if identifier(superclass_name) == identifier(name) {
+                return Err(error(
+                    "A class can't inherit from itself.",
+                    superclass_name.span,
+                ));
+            }
+            self.class = ClassKind::Subclass;
+            self.expression(superclass)?;
+        }
+
+        self.begin_scope();
+        self.scopes
+            .last_mut()
+            .unwrap()
+            .insert(String::from("this"), true);
+        for method in methods {
+            let Stmt::Function { name, params, body } = method else {
+                unreachable!()
+            };
+            let kind = if identifier(name) == "init" {
+                FunctionKind::Initializer
+            } else {
+                FunctionKind::Method
+            };
+            self.function(kind, params, body)?;
+        }
+        self.end_scope();
+        self.class = enclosing;
+        Ok(())
+    }
+
+    fn expression(&mut self, expr: &Expr) -> Result<(), BessyError> {
+        match expr {
+            Expr::Variable(name) => {
+                let name_str = identifier(name);
+                if self
+                    .scopes
+                    .last()
+                    .is_some_and(|scope| scope.get(name_str) == Some(&false))
+                {
+                    return Err(error(
+                        "Can't read local variable in its own initializer.",
+                        name.span,
+                    ));
+                }
+                self.resolve_local(name);
+                Ok(())
+            }
+            Expr::Assign { name, value } => {
+                self.expression(value)?;
+                self.resolve_local(name);
+                Ok(())
+            }
+            Expr::Binary { left, right, .. }
+            | Expr::Logical { left, right, .. } => {
+                self.expression(left)?;
+                self.expression(right)
+            }
+            Expr::Unary { right, .. } => self.expression(right),
+            Expr::Group(expr) | Expr::Stringify(expr) => self.expression(expr),
+            Expr::Call { callee, args, .. } => {
+                self.expression(callee)?;
+                for arg in args {
+                    self.expression(arg)?;
+                }
+                Ok(())
+            }
+            Expr::Get { object, .. } => self.expression(object),
+            Expr::Set { object, value, .. } => {
+                self.expression(object)?;
+                self.expression(value)
+            }
+            Expr::This(keyword) => {
+                if self.class == ClassKind::None {
+                    return Err(error(
+                        "Can't use 'this' outside of a class.",
+                        keyword.span,
+                    ));
+                }
+                Ok(())
+            }
+            Expr::Super { keyword, .. } => match self.class {
+                ClassKind::None => Err(error(
+                    "Can't use 'super' outside of a class.",
+                    keyword.span,
+                )),
+                ClassKind::Class => Err(error(
+                    "Can't use 'super' in a class with no superclass.",
+                    keyword.span,
+                )),
+                ClassKind::Subclass => Ok(()),
+            },
+            Expr::Number(..)
+            | Expr::Boolean(..)
+            | Expr::String(..)
+            | Expr::Nil(_) => Ok(()),
+        }
+    }
+}
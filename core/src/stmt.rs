@@ -6,8 +6,14 @@ pub enum Stmt {
         name: Token,
         init: Option<Expr>,
     },
-    Print(Expr),
-    Expression(Expr),
+    Print {
+        keyword: Token,
+        value: Expr,
+    },
+    Expression {
+        expr: Expr,
+        semicolon: Token,
+    },
     Block(Vec<Stmt>),
     If {
         condition: Expr,
@@ -23,6 +29,11 @@ pub enum Stmt {
         params: Vec<Token>,
         body: Vec<Stmt>,
     },
+    Class {
+        name: Token,
+        superclass: Option<Expr>,
+        methods: Vec<Stmt>,
+    },
     Return {
         keyword: Token,
         value: Option<Expr>,
@@ -0,0 +1,174 @@
+use crate::heap::{Heap, ObjRef};
+use crate::object::{Obj, ObjFunction};
+use std::fmt;
+use std::rc::Rc;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Value {
+    Nil,
+    Boolean(bool),
+    Number(f64),
+    Obj(ObjRef),
+}
+
+// a value as the program embedding the interpreter sees it, strings
+// are shared with the heap and any other object is only described by
+// the way `print` shows it since it can't outlive the heap it is in
+#[derive(Clone, Debug, PartialEq)]
+pub enum HostValue {
+    Nil,
+    Boolean(bool),
+    Number(f64),
+    String(Rc<str>),
+    Object(Rc<str>),
+}
+
+impl From<bool> for HostValue {
+    fn from(value: bool) -> Self {
+        HostValue::Boolean(value)
+    }
+}
+
+impl From<f64> for HostValue {
+    fn from(value: f64) -> Self {
+        HostValue::Number(value)
+    }
+}
+
+impl From<&str> for HostValue {
+    fn from(value: &str) -> Self {
+        HostValue::String(value.into())
+    }
+}
+
+impl fmt::Display for HostValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HostValue::Nil => write!(f, "nil"),
+            HostValue::Boolean(value) => write!(f, "{value}"),
+            HostValue::Number(num) => write!(f, "{}", format_number(*num)),
+            HostValue::String(string) | HostValue::Object(string) => {
+                write!(f, "{string}")
+            }
+        }
+    }
+}
+
+impl Value {
+    // nil and false are falsey and every other value is truthy
+    pub const fn is_falsey(&self) -> bool {
+        matches!(self, Value::Nil | Value::Boolean(false))
+    }
+
+    pub const fn display<'heap>(
+        &self,
+        heap: &'heap Heap,
+    ) -> ValueDisplay<'heap> {
+        ValueDisplay { value: *self, heap }
+    }
+}
+
+// objects live on the heap so printing a value needs access to it
+pub struct ValueDisplay<'heap> {
+    value: Value,
+    heap: &'heap Heap,
+}
+
+impl ValueDisplay<'_> {
+    fn function(
+        &self,
+        f: &mut fmt::Formatter,
+        function: &ObjFunction,
+    ) -> fmt::Result {
+        match function.name {
+            Some(name) => write!(f, "<fn {}>", self.heap.string(name)),
+            None => write!(f, "<script>"),
+        }
+    }
+}
+
+impl fmt::Display for ValueDisplay<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.value {
+            Value::Nil => write!(f, "nil"),
+            Value::Boolean(value) => write!(f, "{value}"),
+            Value::Number(num) => write!(f, "{}", format_number(num)),
+            Value::Obj(obj) => match self.heap.get(obj) {
+                Obj::String(string) => write!(f, "{}", string.value),
+                Obj::Function(function) => self.function(f, function),
+                Obj::Closure(closure) => {
+                    self.function(f, self.heap.function(closure.function))
+                }
+                Obj::Native(_) => write!(f, "<native fn>"),
+                Obj::Class(class) => {
+                    write!(f, "{}", self.heap.string(class.name))
+                }
+                Obj::Instance(instance) => {
+                    let class = self.heap.class(instance.class);
+                    write!(f, "{} instance", self.heap.string(class.name))
+                }
+                Obj::BoundMethod(bound) => {
+                    let closure = self.heap.closure(bound.method);
+                    self.function(f, self.heap.function(closure.function))
+                }
+                Obj::Upvalue(_) => write!(f, "upvalue"),
+            },
+        }
+    }
+}
+
+fn trim_zeros(num: &str) -> &str {
+    if num.contains('.') {
+        num.trim_end_matches('0').trim_end_matches('.')
+    } else {
+        num
+    }
+}
+
+// formats a number the same way as printf's `%g` which is what the
+// reference implementation uses to print numbers
+fn format_number(num: f64) -> String {
+    if num.is_nan() {
+        return String::from("nan");
+    } else if num.is_infinite() {
+        return String::from(if num > 0.0 { "inf" } else { "-inf" });
+    } else if num == 0.0 {
+        return String::from(if num.is_sign_negative() { "-0" } else { "0" });
+    }
+    let scientific = format!("{num:.5e}");
+    let (mantissa, exponent) = scientific.split_once('e').unwrap();
+    let exponent = exponent.parse::<i32>().unwrap();
+    if !(-4..6).contains(&exponent) {
+        let sign = if exponent < 0 { '-' } else { '+' };
+        format!("{}e{sign}{:02}", trim_zeros(mantissa), exponent.abs())
+    } else {
+        let precision = (5 - exponent) as usize;
+        trim_zeros(&format!("{num:.precision$}")).to_string()
+    }
+}
+
+#[cfg(test)]
+mod test_value {
+    use super::*;
+
+    #[test]
+    fn test_format_number() {
+        assert_eq!(format_number(1.0), "1");
+        assert_eq!(format_number(-2.5), "-2.5");
+        assert_eq!(format_number(100.0), "100");
+        assert_eq!(format_number(0.1 + 0.2), "0.3");
+        assert_eq!(format_number(1234567.0), "1.23457e+06");
+        assert_eq!(format_number(0.00001), "1e-05");
+        assert_eq!(format_number(1e21), "1e+21");
+        assert_eq!(format_number(-0.0), "-0");
+        assert_eq!(format_number(f64::INFINITY), "inf");
+    }
+
+    #[test]
+    fn test_truthiness() {
+        assert!(Value::Nil.is_falsey());
+        assert!(Value::Boolean(false).is_falsey());
+        assert!(!Value::Boolean(true).is_falsey());
+        assert!(!Value::Number(0.0).is_falsey());
+    }
+}
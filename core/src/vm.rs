@@ -0,0 +1,1182 @@
+// a virtual machine executes the bytecode emitted by the compiler
+// one instruction at a time using a stack to hold temporary values
+use crate::chunk::OpCode;
+use crate::compiler::Compiler;
+use crate::debug;
+use crate::error::{BessyError, TraceFrame};
+use crate::heap::{Heap, ObjRef};
+use crate::lexer::Span;
+use crate::object::{
+    NativeFn, Obj, ObjBoundMethod, ObjClass, ObjClosure, ObjInstance,
+    ObjNative, ObjUpvalue,
+};
+use crate::stmt::Stmt;
+use crate::value::{HostValue, Value};
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::rc::{Rc, Weak};
+
+const FRAMES_MAX: usize = 64;
+const OBJECT_ARGUMENT: &str =
+    "Only nil, booleans, numbers and strings can be passed to lox.";
+
+struct CallFrame {
+    closure: ObjRef,
+    function: ObjRef,
+    ip: usize,
+    slot: usize,
+}
+
+// settings that change how the virtual machine runs a program
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Options {
+    // prints the value stack and every instruction before it runs
+    pub trace: bool,
+}
+
+pub struct Vm {
+    options: Options,
+    frames: Vec<CallFrame>,
+    stack: Vec<Value>,
+    globals: HashMap<ObjRef, Value>,
+    // upvalues still pointing into the stack, ordered by their slot
+    open_upvalues: Vec<ObjRef>,
+    heap: Heap,
+    init_string: ObjRef,
+    // scripts compiled for the host to run later, each one is kept
+    // alive for as long as the host holds on to its handle
+    programs: Vec<(Weak<()>, ObjRef)>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn clock_native(_args: &[HostValue]) -> Result<HostValue, String> {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default();
+    Ok(HostValue::Number(now.as_secs_f64()))
+}
+
+fn api_error(message: &str) -> BessyError {
+    BessyError::Api {
+        msg: message.into(),
+    }
+}
+
+impl Default for Vm {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Vm {
+    pub fn new() -> Self {
+        Self::with_options(Options::default())
+    }
+
+    pub fn with_options(options: Options) -> Self {
+        let mut heap = Heap::default();
+        let init_string = heap.intern("init");
+        let mut vm = Self {
+            options,
+            frames: Vec::with_capacity(FRAMES_MAX),
+            stack: Vec::with_capacity(FRAMES_MAX * u8::MAX as usize),
+            globals: HashMap::new(),
+            open_upvalues: Vec::new(),
+            heap,
+            init_string,
+            programs: Vec::new(),
+        };
+        // the system clock is not available in the browser
+        #[cfg(not(target_arch = "wasm32"))]
+        vm.define_native("clock", 0, Rc::new(clock_native));
+        vm
+    }
+
+    // the values printed by the program, and the trace if it is
+    // switched on, are written to `output`
+    pub fn interpret(
+        &mut self,
+        stmts: &[Stmt],
+        output: &mut dyn Write,
+    ) -> Result<(), BessyError> {
+        let roots = self.roots();
+        let function = Compiler::new(&mut self.heap, roots).compile(stmts)?;
+        self.run_script(function, output)
+    }
+
+    // compiles a program without running it, the handle that is
+    // returned is what the program is run with
+    pub fn compile(&mut self, stmts: &[Stmt]) -> Result<Rc<()>, BessyError> {
+        let roots = self.roots();
+        let function = Compiler::new(&mut self.heap, roots).compile(stmts)?;
+        let handle = Rc::new(());
+        self.programs
+            .retain(|(handle, _)| handle.strong_count() > 0);
+        self.programs.push((Rc::downgrade(&handle), function));
+        Ok(handle)
+    }
+
+    pub fn run_compiled(
+        &mut self,
+        handle: &Rc<()>,
+        output: &mut dyn Write,
+    ) -> Result<(), BessyError> {
+        let handle = Rc::downgrade(handle);
+        let Some(&(_, function)) = self
+            .programs
+            .iter()
+            .find(|(other, _)| other.ptr_eq(&handle))
+        else {
+            return Err(api_error(
+                "The program was compiled by another interpreter.",
+            ));
+        };
+        self.run_script(function, output)
+    }
+
+    fn run_script(
+        &mut self,
+        function: ObjRef,
+        output: &mut dyn Write,
+    ) -> Result<(), BessyError> {
+        let closure = self.alloc(Obj::Closure(ObjClosure {
+            function,
+            upvalues: Vec::new(),
+            class: None,
+        }));
+        self.stack.push(Value::Obj(closure));
+        let result = self.call(closure, 0).and_then(|_| self.run(output));
+        if result.is_err() {
+            self.reset();
+        }
+        result.map(|_| ())
+    }
+
+    // calls a function declared by a program that was run before with
+    // arguments passed in by the host
+    pub fn call_function(
+        &mut self,
+        name: &str,
+        args: &[HostValue],
+        output: &mut dyn Write,
+    ) -> Result<HostValue, BessyError> {
+        let closure = match self.global(name) {
+            Some(Value::Obj(obj))
+                if matches!(self.heap.get(obj), Obj::Closure(_)) =>
+            {
+                obj
+            }
+            Some(_) => {
+                return Err(api_error(&format!("'{name}' is not a function.")))
+            }
+            None => {
+                return Err(api_error(&format!("Undefined function '{name}'.")))
+            }
+        };
+        let arity = self
+            .heap
+            .function(self.heap.closure(closure).function)
+            .arity;
+        if args.len() != arity {
+            return Err(api_error(&format!(
+                "Expected {arity} arguments but got {}.",
+                args.len()
+            )));
+        }
+        // the arguments are kept on the stack as they are converted so
+        // that a collection can't free the ones converted earlier
+        self.stack.push(Value::Obj(closure));
+        for arg in args {
+            let Some(arg) = self.value_from_host(arg) else {
+                self.reset();
+                return Err(api_error(OBJECT_ARGUMENT));
+            };
+            self.stack.push(arg);
+        }
+        let result = self
+            .call(closure, args.len())
+            .and_then(|_| self.run(output));
+        match result {
+            Ok(value) => Ok(self.host_value(value)),
+            Err(err) => {
+                self.reset();
+                Err(err)
+            }
+        }
+    }
+
+    pub fn get_global(&self, name: &str) -> Option<HostValue> {
+        self.global(name).map(|value| self.host_value(value))
+    }
+
+    pub fn set_global(
+        &mut self,
+        name: &str,
+        value: &HostValue,
+    ) -> Result<(), BessyError> {
+        let value = self
+            .value_from_host(value)
+            .ok_or_else(|| api_error(OBJECT_ARGUMENT))?;
+        self.stack.push(value);
+        let name = self.intern(name);
+        self.globals.insert(name, value);
+        self.pop();
+        Ok(())
+    }
+
+    fn global(&self, name: &str) -> Option<Value> {
+        let name = self.heap.lookup(name)?;
+        self.globals.get(&name).copied()
+    }
+
+    // a closure that outlives the error, by being stored in a global,
+    // still needs the values it captured from the stack
+    fn reset(&mut self) {
+        self.close_upvalues(0);
+        self.stack.clear();
+        self.frames.clear();
+    }
+
+    fn host_value(&self, value: Value) -> HostValue {
+        match value {
+            Value::Nil => HostValue::Nil,
+            Value::Boolean(value) => HostValue::Boolean(value),
+            Value::Number(num) => HostValue::Number(num),
+            Value::Obj(obj) => match self.heap.get(obj) {
+                Obj::String(string) => HostValue::String(string.value.clone()),
+                _ => HostValue::Object(
+                    value.display(&self.heap).to_string().into(),
+                ),
+            },
+        }
+    }
+
+    // an object other than a string only exists on the host side as
+    // a description and can't be turned back into a value
+    fn value_from_host(&mut self, value: &HostValue) -> Option<Value> {
+        match value {
+            HostValue::Nil => Some(Value::Nil),
+            HostValue::Boolean(value) => Some(Value::Boolean(*value)),
+            HostValue::Number(num) => Some(Value::Number(*num)),
+            HostValue::String(string) => Some(Value::Obj(self.intern(string))),
+            HostValue::Object(_) => None,
+        }
+    }
+
+    // compiles the program without running it and lists the bytecode
+    // of every function in it
+    pub fn disassemble(
+        &mut self,
+        stmts: &[Stmt],
+    ) -> Result<String, BessyError> {
+        let roots = self.roots();
+        let function = Compiler::new(&mut self.heap, roots).compile(stmts)?;
+        Ok(debug::disassemble_function(&self.heap, function))
+    }
+
+    // every value the virtual machine can still reach without going
+    // through another object
+    fn roots(&self) -> Vec<Value> {
+        let frames = self.frames.iter().map(|frame| frame.closure);
+        let globals = self
+            .globals
+            .iter()
+            .flat_map(|(&name, &value)| [Value::Obj(name), value]);
+        let programs = self
+            .programs
+            .iter()
+            .filter(|(handle, _)| handle.strong_count() > 0)
+            .map(|&(_, function)| Value::Obj(function));
+        self.stack
+            .iter()
+            .copied()
+            .chain(frames.map(Value::Obj))
+            .chain(globals)
+            .chain(programs)
+            .chain(self.open_upvalues.iter().copied().map(Value::Obj))
+            .chain([Value::Obj(self.init_string)])
+            .collect()
+    }
+
+    fn collect_garbage(&mut self) {
+        for root in self.roots() {
+            self.heap.mark_value(root);
+        }
+        self.heap.collect();
+    }
+
+    fn alloc(&mut self, obj: Obj) -> ObjRef {
+        if self.heap.should_collect() {
+            self.heap.mark_references(&obj);
+            self.collect_garbage();
+        }
+        self.heap.alloc(obj)
+    }
+
+    fn intern(&mut self, string: &str) -> ObjRef {
+        if self.heap.should_collect() {
+            self.collect_garbage();
+        }
+        self.heap.intern(string)
+    }
+
+    pub fn define_native(
+        &mut self,
+        name: &str,
+        arity: usize,
+        function: NativeFn,
+    ) {
+        let name = self.intern(name);
+        let native = self.alloc(Obj::Native(ObjNative {
+            name,
+            arity,
+            function,
+        }));
+        self.globals.insert(name, Value::Obj(native));
+    }
+
+    fn frame(&self) -> &CallFrame {
+        self.frames.last().unwrap()
+    }
+
+    fn frame_mut(&mut self) -> &mut CallFrame {
+        self.frames.last_mut().unwrap()
+    }
+
+    // the span of the instruction a frame is executing, for a caller
+    // that is the call it is waiting on
+    fn frame_span(&self, frame: &CallFrame) -> Span {
+        self.heap.function(frame.function).chunk.spans[frame.ip - 1]
+    }
+
+    fn output_error(&self, err: io::Error) -> BessyError {
+        self.error(&format!("Could not write output: {err}."))
+    }
+
+    fn error(&self, message: &str) -> BessyError {
+        let trace = self
+            .frames
+            .iter()
+            .rev()
+            .map(|frame| TraceFrame {
+                function: self
+                    .heap
+                    .function(frame.function)
+                    .name
+                    .map(|name| self.heap.string(name).into()),
+                line_number: self.frame_span(frame).start.line_number,
+            })
+            .collect();
+        BessyError::Runtime {
+            msg: message.into(),
+            span: self.frame_span(self.frame()),
+            trace,
+        }
+    }
+
+    fn pop(&mut self) -> Value {
+        self.stack.pop().unwrap()
+    }
+
+    fn peek(&self, distance: usize) -> Value {
+        self.stack[self.stack.len() - 1 - distance]
+    }
+
+    fn read_constant(&self, index: u8) -> Value {
+        let function = self.heap.function(self.frame().function);
+        function.chunk.constants[index as usize]
+    }
+
+    fn read_object(&self, index: u8) -> ObjRef {
+        match self.read_constant(index) {
+            Value::Obj(obj) => obj,
+            _ => unreachable!(),
+        }
+    }
+
+    fn binary_op(
+        &mut self,
+        op: impl Fn(f64, f64) -> Value,
+    ) -> Result<(), BessyError> {
+        match (self.peek(1), self.peek(0)) {
+            (Value::Number(a), Value::Number(b)) => {
+                self.stack.truncate(self.stack.len() - 2);
+                self.stack.push(op(a, b));
+                Ok(())
+            }
+            _ => Err(self.error("Operands must be numbers.")),
+        }
+    }
+
+    fn concatenate(&mut self) -> Result<(), BessyError> {
+        let (Value::Obj(a), Value::Obj(b)) = (self.peek(1), self.peek(0))
+        else {
+            unreachable!()
+        };
+        let result = format!("{}{}", self.heap.string(a), self.heap.string(b));
+        let result = self.intern(&result);
+        self.stack.truncate(self.stack.len() - 2);
+        self.stack.push(Value::Obj(result));
+        Ok(())
+    }
+
+    fn is_string(&self, value: Value) -> bool {
+        match value {
+            Value::Obj(obj) => matches!(self.heap.get(obj), Obj::String(_)),
+            _ => false,
+        }
+    }
+
+    fn call(
+        &mut self,
+        closure: ObjRef,
+        arg_count: usize,
+    ) -> Result<(), BessyError> {
+        let function = self.heap.closure(closure).function;
+        let arity = self.heap.function(function).arity;
+        if arg_count != arity {
+            return Err(self.error(&format!(
+                "Expected {arity} arguments but got {arg_count}."
+            )));
+        }
+        if self.frames.len() == FRAMES_MAX {
+            return Err(self.error("Stack overflow."));
+        }
+        self.frames.push(CallFrame {
+            closure,
+            function,
+            ip: 0,
+            slot: self.stack.len() - arg_count - 1,
+        });
+        Ok(())
+    }
+
+    fn call_native(
+        &mut self,
+        native: &ObjNative,
+        arg_count: usize,
+    ) -> Result<(), BessyError> {
+        if arg_count != native.arity {
+            return Err(self.error(&format!(
+                "Expected {} arguments but got {arg_count}.",
+                native.arity
+            )));
+        }
+        let args = self.stack.len() - arg_count;
+        let host_args: Vec<HostValue> = self.stack[args..]
+            .iter()
+            .map(|&value| self.host_value(value))
+            .collect();
+        let result =
+            (native.function)(&host_args).map_err(|msg| self.error(&msg))?;
+        let Some(result) = self.value_from_host(&result) else {
+            return Err(self.error("A native function can't return an object."));
+        };
+        self.stack.truncate(args - 1);
+        self.stack.push(result);
+        Ok(())
+    }
+
+    // calling a class creates a new instance of it and runs the
+    // initializer, if there is one, with the instance as receiver
+    fn call_class(
+        &mut self,
+        class: ObjRef,
+        arg_count: usize,
+    ) -> Result<(), BessyError> {
+        let initializer = self
+            .heap
+            .class(class)
+            .methods
+            .get(&self.init_string)
+            .copied();
+        let instance = self.alloc(Obj::Instance(ObjInstance {
+            class,
+            fields: HashMap::new(),
+        }));
+        let slot = self.stack.len() - arg_count - 1;
+        self.stack[slot] = Value::Obj(instance);
+        match initializer {
+            Some(initializer) => self.call(initializer, arg_count),
+            None if arg_count != 0 => Err(self
+                .error(&format!("Expected 0 arguments but got {arg_count}."))),
+            None => Ok(()),
+        }
+    }
+
+    fn call_value(&mut self, arg_count: usize) -> Result<(), BessyError> {
+        if let Value::Obj(callee) = self.peek(arg_count) {
+            match self.heap.get(callee) {
+                Obj::Closure(_) => return self.call(callee, arg_count),
+                Obj::Native(native) => {
+                    let native = native.clone();
+                    return self.call_native(&native, arg_count);
+                }
+                Obj::Class(_) => return self.call_class(callee, arg_count),
+                Obj::BoundMethod(bound) => {
+                    let method = bound.method;
+                    let slot = self.stack.len() - arg_count - 1;
+                    self.stack[slot] = bound.receiver;
+                    return self.call(method, arg_count);
+                }
+                _ => {}
+            }
+        }
+        Err(self.error("Can only call functions and classes."))
+    }
+
+    fn open_slot(&self, upvalue: ObjRef) -> usize {
+        match self.heap.upvalue(upvalue) {
+            ObjUpvalue::Open(slot) => *slot,
+            ObjUpvalue::Closed(_) => unreachable!(),
+        }
+    }
+
+    // reuses the upvalue of a slot if another closure has captured it
+    // already so that every closure sees the same variable
+    fn capture_upvalue(&mut self, slot: usize) -> ObjRef {
+        let position = self
+            .open_upvalues
+            .partition_point(|&upvalue| self.open_slot(upvalue) < slot);
+        if let Some(&upvalue) = self.open_upvalues.get(position) {
+            if self.open_slot(upvalue) == slot {
+                return upvalue;
+            }
+        }
+        let upvalue = self.alloc(Obj::Upvalue(ObjUpvalue::Open(slot)));
+        self.open_upvalues.insert(position, upvalue);
+        upvalue
+    }
+
+    // moves every captured variable at or above `last` off the stack
+    fn close_upvalues(&mut self, last: usize) {
+        while let Some(&upvalue) = self.open_upvalues.last() {
+            let slot = self.open_slot(upvalue);
+            if slot < last {
+                break;
+            }
+            *self.heap.upvalue_mut(upvalue) =
+                ObjUpvalue::Closed(self.stack[slot]);
+            self.open_upvalues.pop();
+        }
+    }
+
+    fn as_instance(&self, value: Value) -> Option<&ObjInstance> {
+        match value {
+            Value::Obj(obj) => match self.heap.get(obj) {
+                Obj::Instance(instance) => Some(instance),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    // replaces the instance on top of the stack with its method
+    // bound to it
+    fn bind_method(
+        &mut self,
+        class: ObjRef,
+        name: ObjRef,
+    ) -> Result<(), BessyError> {
+        let Some(&method) = self.heap.class(class).methods.get(&name) else {
+            return Err(self.error(&format!(
+                "Undefined property '{}'.",
+                self.heap.string(name)
+            )));
+        };
+        let bound = self.alloc(Obj::BoundMethod(ObjBoundMethod {
+            receiver: self.peek(0),
+            method,
+        }));
+        self.pop();
+        self.stack.push(Value::Obj(bound));
+        Ok(())
+    }
+
+    fn trace_instruction(&self, output: &mut dyn Write) -> io::Result<()> {
+        let stack: String = self
+            .stack
+            .iter()
+            .map(|value| format!("[ {} ]", value.display(&self.heap)))
+            .collect();
+        writeln!(output, "          {stack}")?;
+        let frame = self.frame();
+        let chunk = &self.heap.function(frame.function).chunk;
+        writeln!(
+            output,
+            "{}",
+            debug::disassemble_instruction(&self.heap, chunk, frame.ip)
+        )
+    }
+
+    // runs until the frame that was called first returns and gives
+    // back the value it returned
+    fn run(&mut self, output: &mut dyn Write) -> Result<Value, BessyError> {
+        loop {
+            if self.options.trace {
+                self.trace_instruction(output)
+                    .map_err(|err| self.output_error(err))?;
+            }
+            let frame = self.frames.last_mut().unwrap();
+            let opcode =
+                self.heap.function(frame.function).chunk.code[frame.ip];
+            frame.ip += 1;
+            match opcode {
+                OpCode::Constant(index) => {
+                    let constant = self.read_constant(index);
+                    self.stack.push(constant);
+                }
+                OpCode::Nil => self.stack.push(Value::Nil),
+                OpCode::True => self.stack.push(Value::Boolean(true)),
+                OpCode::False => self.stack.push(Value::Boolean(false)),
+                OpCode::Pop => {
+                    self.pop();
+                }
+                OpCode::GetLocal(slot) => {
+                    let slot = self.frame().slot + slot as usize;
+                    self.stack.push(self.stack[slot]);
+                }
+                OpCode::SetLocal(slot) => {
+                    let slot = self.frame().slot + slot as usize;
+                    self.stack[slot] = self.peek(0);
+                }
+                OpCode::GetUpvalue(index) => {
+                    let closure = self.heap.closure(self.frame().closure);
+                    let upvalue = closure.upvalues[index as usize];
+                    let value = match *self.heap.upvalue(upvalue) {
+                        ObjUpvalue::Open(slot) => self.stack[slot],
+                        ObjUpvalue::Closed(value) => value,
+                    };
+                    self.stack.push(value);
+                }
+                OpCode::SetUpvalue(index) => {
+                    let closure = self.heap.closure(self.frame().closure);
+                    let upvalue = closure.upvalues[index as usize];
+                    let value = self.peek(0);
+                    match self.heap.upvalue_mut(upvalue) {
+                        ObjUpvalue::Open(slot) => self.stack[*slot] = value,
+                        ObjUpvalue::Closed(closed) => *closed = value,
+                    }
+                }
+                OpCode::GetGlobal(index) => {
+                    let name = self.read_object(index);
+                    match self.globals.get(&name) {
+                        Some(&value) => self.stack.push(value),
+                        None => {
+                            return Err(self.error(&format!(
+                                "Undefined variable '{}'.",
+                                self.heap.string(name)
+                            )))
+                        }
+                    }
+                }
+                OpCode::DefineGlobal(index) => {
+                    let name = self.read_object(index);
+                    let value = self.pop();
+                    self.globals.insert(name, value);
+                }
+                OpCode::SetGlobal(index) => {
+                    let name = self.read_object(index);
+                    let value = self.peek(0);
+                    match self.globals.get_mut(&name) {
+                        Some(global) => *global = value,
+                        None => {
+                            return Err(self.error(&format!(
+                                "Undefined variable '{}'.",
+                                self.heap.string(name)
+                            )))
+                        }
+                    }
+                }
+                OpCode::Equal => {
+                    let b = self.pop();
+                    let a = self.pop();
+                    self.stack.push(Value::Boolean(a == b));
+                }
+                OpCode::Greater => {
+                    self.binary_op(|a, b| Value::Boolean(a > b))?
+                }
+                OpCode::Less => self.binary_op(|a, b| Value::Boolean(a < b))?,
+                OpCode::Add => {
+                    let (a, b) = (self.peek(1), self.peek(0));
+                    if self.is_string(a) && self.is_string(b) {
+                        self.concatenate()?
+                    } else if let (Value::Number(_), Value::Number(_)) = (a, b)
+                    {
+                        self.binary_op(|a, b| Value::Number(a + b))?
+                    } else {
+                        return Err(self.error(
+                            "Operands must be two numbers or two strings.",
+                        ));
+                    }
+                }
+                OpCode::Subtract => {
+                    self.binary_op(|a, b| Value::Number(a - b))?
+                }
+                OpCode::Multiply => {
+                    self.binary_op(|a, b| Value::Number(a * b))?
+                }
+                OpCode::Divide => {
+                    self.binary_op(|a, b| Value::Number(a / b))?
+                }
+                OpCode::Modulo => {
+                    self.binary_op(|a, b| Value::Number(a % b))?
+                }
+                OpCode::Not => {
+                    let value = self.pop();
+                    self.stack.push(Value::Boolean(value.is_falsey()));
+                }
+                OpCode::Negate => match self.pop() {
+                    Value::Number(num) => self.stack.push(Value::Number(-num)),
+                    _ => return Err(self.error("Operand must be a number.")),
+                },
+                OpCode::Stringify => {
+                    // the value stays on the stack while its string is
+                    // allocated so that it can't be collected
+                    let value = self.peek(0);
+                    if !self.is_string(value) {
+                        let string = value.display(&self.heap).to_string();
+                        let string = self.intern(&string);
+                        self.pop();
+                        self.stack.push(Value::Obj(string));
+                    }
+                }
+                OpCode::Print => {
+                    let value = self.pop();
+                    writeln!(output, "{}", value.display(&self.heap))
+                        .map_err(|err| self.output_error(err))?;
+                }
+                OpCode::Jump(offset) => self.frame_mut().ip += offset as usize,
+                OpCode::JumpIfFalse(offset) => {
+                    if self.peek(0).is_falsey() {
+                        self.frame_mut().ip += offset as usize;
+                    }
+                }
+                OpCode::Loop(offset) => self.frame_mut().ip -= offset as usize,
+                OpCode::Call(arg_count) => {
+                    self.call_value(arg_count as usize)?
+                }
+                OpCode::Closure(index) => {
+                    // functions declared inside a method share the
+                    // class of the method they were created in
+                    let function = self.read_object(index);
+                    let frame = self.frame();
+                    let (enclosing, slot) = (frame.closure, frame.slot);
+                    let captures =
+                        self.heap.function(function).upvalues.clone();
+                    let upvalues = captures
+                        .iter()
+                        .map(|capture| {
+                            if capture.is_local {
+                                self.capture_upvalue(
+                                    slot + capture.index as usize,
+                                )
+                            } else {
+                                self.heap.closure(enclosing).upvalues
+                                    [capture.index as usize]
+                            }
+                        })
+                        .collect();
+                    let class = self.heap.closure(enclosing).class;
+                    let closure = self.alloc(Obj::Closure(ObjClosure {
+                        function,
+                        upvalues,
+                        class,
+                    }));
+                    self.stack.push(Value::Obj(closure));
+                }
+                OpCode::CloseUpvalue => {
+                    self.close_upvalues(self.stack.len() - 1);
+                    self.pop();
+                }
+                OpCode::Class(index) => {
+                    let name = self.read_object(index);
+                    let class = self.alloc(Obj::Class(ObjClass {
+                        name,
+                        superclass: None,
+                        methods: HashMap::new(),
+                    }));
+                    self.stack.push(Value::Obj(class));
+                }
+                OpCode::Method(index) => {
+                    let name = self.read_object(index);
+                    let (Value::Obj(class), Value::Obj(method)) =
+                        (self.peek(1), self.peek(0))
+                    else {
+                        unreachable!()
+                    };
+                    self.heap.class_mut(class).methods.insert(name, method);
+                    self.heap.closure_mut(method).class = Some(class);
+                    self.pop();
+                }
+                OpCode::Inherit => {
+                    let Value::Obj(subclass) = self.peek(0) else {
+                        unreachable!()
+                    };
+                    let superclass = match self.peek(1) {
+                        Value::Obj(obj)
+                            if matches!(self.heap.get(obj), Obj::Class(_)) =>
+                        {
+                            obj
+                        }
+                        _ => {
+                            return Err(
+                                self.error("Superclass must be a class.")
+                            )
+                        }
+                    };
+                    // methods are copied down into the subclass, any
+                    // method it declares itself overrides them later
+                    let methods = self.heap.class(superclass).methods.clone();
+                    let subclass = self.heap.class_mut(subclass);
+                    subclass.superclass = Some(superclass);
+                    subclass.methods.extend(methods);
+                    self.pop();
+                    self.pop();
+                }
+                OpCode::GetSuper(index) => {
+                    let name = self.read_object(index);
+                    let class = self.heap.closure(self.frame().closure).class;
+                    let superclass = class
+                        .and_then(|class| self.heap.class(class).superclass)
+                        .unwrap();
+                    self.bind_method(superclass, name)?;
+                }
+                OpCode::GetProperty(index) => {
+                    let name = self.read_object(index);
+                    let Some(instance) = self.as_instance(self.peek(0)) else {
+                        return Err(
+                            self.error("Only instances have properties.")
+                        );
+                    };
+                    if let Some(&value) = instance.fields.get(&name) {
+                        self.pop();
+                        self.stack.push(value);
+                    } else {
+                        self.bind_method(instance.class, name)?;
+                    }
+                }
+                OpCode::SetProperty(index) => {
+                    let name = self.read_object(index);
+                    if self.as_instance(self.peek(1)).is_none() {
+                        return Err(self.error("Only instances have fields."));
+                    }
+                    let Value::Obj(instance) = self.peek(1) else {
+                        unreachable!()
+                    };
+                    let value = self.pop();
+                    self.heap.instance_mut(instance).fields.insert(name, value);
+                    self.pop();
+                    self.stack.push(value);
+                }
+                OpCode::Return => {
+                    let result = self.pop();
+                    let frame = self.frames.pop().unwrap();
+                    self.close_upvalues(frame.slot);
+                    self.stack.truncate(frame.slot);
+                    if self.frames.is_empty() {
+                        return Ok(result);
+                    }
+                    self.stack.push(result);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_vm {
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    use super::*;
+
+    // runs the program and returns what it printed
+    fn runner(src: &str, stress: bool) -> Result<String, BessyError> {
+        let (tokens, errors) = Lexer::new(src).scan_all();
+        let (ast, parse_errors) = Parser::new(tokens.into_iter()).parse();
+        if let Some(err) = errors.into_iter().chain(parse_errors).next() {
+            return Err(err);
+        }
+        let mut vm = Vm::new();
+        if stress {
+            vm.heap.stress = true;
+        }
+        let mut output = Vec::new();
+        vm.interpret(&ast, &mut output)?;
+        Ok(String::from_utf8(output).unwrap())
+    }
+
+    fn test_runner(src: &str) -> Result<String, BessyError> {
+        runner(src, false)
+    }
+
+    fn stress_runner(src: &str) -> Result<String, BessyError> {
+        runner(src, true)
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        assert_eq!(test_runner("print 1 + 2 * 3 - 4 / 2 % 3;").unwrap(), "5\n");
+        assert!(test_runner("print -true;").is_err());
+        assert!(test_runner("print 1 + \"one\";").is_err());
+    }
+
+    #[test]
+    fn test_globals() {
+        assert_eq!(
+            test_runner("var a = 1; a = a + 1; print a;").unwrap(),
+            "2\n"
+        );
+        assert!(test_runner("print b;").is_err());
+        assert!(test_runner("b = 1;").is_err());
+        // every use of a name shares one constant
+        let script =
+            format!("var a = 0;\n{}print a;", "a = a + 1;\n".repeat(300));
+        assert_eq!(test_runner(&script).unwrap(), "300\n");
+    }
+
+    #[test]
+    fn test_control_flow() {
+        assert_eq!(
+            test_runner(
+                "var sum = 0; for (var i = 0; i < 10; i = i + 1) { \
+                 if (i > 5) sum = sum + i; else sum = sum - i; } print sum;"
+            )
+            .unwrap(),
+            "15\n"
+        );
+    }
+
+    #[test]
+    fn test_logical() {
+        assert_eq!(
+            test_runner("print nil or \"default\";").unwrap(),
+            "default\n"
+        );
+        assert_eq!(
+            test_runner("print false and undefined;").unwrap(),
+            "false\n"
+        );
+        assert_eq!(test_runner("print true or undefined;").unwrap(), "true\n");
+        assert_eq!(
+            test_runner("print 1 and nil; print nil or false;").unwrap(),
+            "nil\nfalse\n"
+        );
+        assert!(test_runner("print true and undefined;").is_err());
+        assert!(test_runner("print false or undefined;").is_err());
+    }
+
+    #[test]
+    fn test_classes() {
+        assert_eq!(
+            test_runner(
+                "class Point { init(x, y) { this.x = x; this.y = y; } \
+                 sum() { return this.x + this.y; } } \
+                 var p = Point(1, 2); p.x = 10; print p.sum(); print p;"
+            )
+            .unwrap(),
+            "12\nPoint instance\n"
+        );
+        assert!(test_runner("class A {} print A().field;").is_err());
+        assert!(test_runner("class A {} A(1);").is_err());
+        assert!(test_runner("var a = 1; a.field = 2;").is_err());
+        assert!(test_runner("print this;").is_err());
+        assert!(test_runner("class A { init() { return 1; } }").is_err());
+    }
+
+    #[test]
+    fn test_inheritance() {
+        assert_eq!(
+            test_runner(
+                "class A { method() { return \"A\"; } } \
+                 class B < A { method() { return \"B\" + super.method(); } } \
+                 class C < B {} print C().method();"
+            )
+            .unwrap(),
+            "BA\n"
+        );
+        assert!(test_runner("class A < A {}").is_err());
+        assert!(test_runner("var A = 1; class B < A {}").is_err());
+        assert!(test_runner("class A { f() { super.f(); } }").is_err());
+        assert!(test_runner("print super.f;").is_err());
+    }
+
+    #[test]
+    fn test_closures() {
+        assert_eq!(
+            test_runner(
+                "fun makeCounter() { var i = 0; \
+                 fun c() { i = i + 1; return i; } return c; } \
+                 var counter = makeCounter(); counter(); print counter();"
+            )
+            .unwrap(),
+            "2\n"
+        );
+        // two counters don't share their variable, and a closure sees
+        // assignments made after it was created
+        assert_eq!(
+            test_runner(
+                "fun makeCounter() { var i = 0; \
+                 fun c() { i = i + 1; return i; } return c; } \
+                 var a = makeCounter(); var b = makeCounter(); \
+                 a(); a(); print a(); print b(); \
+                 var x = \"before\"; fun show() { print x; } \
+                 x = \"after\"; show();"
+            )
+            .unwrap(),
+            "3\n1\nafter\n"
+        );
+        assert_eq!(
+            test_runner(
+                "class A { method() { fun inner() { return this; } \
+                 return inner; } } print A().method()();"
+            )
+            .unwrap(),
+            "A instance\n"
+        );
+    }
+
+    #[test]
+    fn test_functions() {
+        assert_eq!(
+            test_runner(
+                "fun fib(n) { if (n < 2) return n; \
+                 return fib(n - 1) + fib(n - 2); } print fib(10);"
+            )
+            .unwrap(),
+            "55\n"
+        );
+        assert!(test_runner("fun f(a) {} f();").is_err());
+        assert!(test_runner("var a = 1; a();").is_err());
+        assert_eq!(test_runner("print clock() > 0;").unwrap(), "true\n");
+        assert!(test_runner("clock(1);").is_err());
+        assert!(test_runner("fun f() { f(); } f();").is_err());
+    }
+
+    #[test]
+    fn test_scopes() {
+        assert!(test_runner("var a = 1; { var a = a + 1; print a; }").is_err());
+        assert_eq!(
+            test_runner("var a = 1; var a = a + 1; print a;").unwrap(),
+            "2\n"
+        );
+        assert!(test_runner("{ var a = 1; var a = 2; }").is_err());
+        assert!(test_runner("{ var a = 1; { var a = 2; } }").is_ok());
+        assert!(test_runner("fun f(a, a) {}").is_err());
+        assert!(test_runner("return 1;").is_err());
+        assert!(test_runner("fun f() { return; } f();").is_ok());
+    }
+
+    #[test]
+    fn test_strings() {
+        assert_eq!(
+            test_runner(
+                "var name = \"lox\"; \
+                 print \"hello ${name}, ${\"nested ${name + \"!\"}\"}\\n\";"
+            )
+            .unwrap(),
+            "hello lox, nested lox!\n\n"
+        );
+        assert_eq!(
+            test_runner("var n = 3; print \"n is ${n + 1}!\";").unwrap(),
+            "n is 4!\n"
+        );
+        assert_eq!(
+            test_runner(
+                "class A {} fun f() {} \
+                 print \"${nil} ${true} ${1.5} ${A()} ${f} ${\"${2}\"}\";"
+            )
+            .unwrap(),
+            "nil true 1.5 A instance <fn f> 2\n"
+        );
+        assert_eq!(
+            test_runner("print \"tab\\t\\\"quote\\\" \\${not} \\u{e9}\";")
+                .unwrap(),
+            "tab\t\"quote\" ${not} \u{e9}\n"
+        );
+        assert!(test_runner("print \"${}\";").is_err());
+        assert!(test_runner("print \"\\x\";").is_err());
+    }
+
+    #[test]
+    fn test_gc_stress() {
+        assert_eq!(
+            stress_runner(
+                "class Node { init(value, next) { this.value = value; \
+                 this.next = next; } } \
+                 fun makeAdder(n) { fun add(x) { return x + n; } return add; } \
+                 var list = nil; \
+                 for (var i = 0; i < 50; i = i + 1) { \
+                 list = Node(makeAdder(\"#\")(\"s\" + \"t\"), list); } \
+                 class A { m() { return \"a\"; } } \
+                 class B < A { m() { return super.m() + \"b\"; } } \
+                 var s = \"\"; \
+                 while (list != nil) { s = s + list.value; list = list.next; } \
+                 print B().m() + s;"
+            )
+            .unwrap(),
+            format!("ab{}\n", "st#".repeat(50))
+        );
+    }
+
+    #[test]
+    fn test_trace() {
+        let (tokens, _) =
+            Lexer::new("fun f(a) { return a * 2; } print f(1);").scan_all();
+        let (ast, _) = Parser::new(tokens.into_iter()).parse();
+        let mut vm = Vm::with_options(Options { trace: true });
+        let mut output = Vec::new();
+        assert!(vm.interpret(&ast, &mut output).is_ok());
+        let output = String::from_utf8(output).unwrap();
+        let start = "          [ <script> ]\n0000    1 Closure";
+        assert!(output.starts_with(start));
+        assert!(output.contains("          [ <script> ][ <fn f> ][ 1 ]\n"));
+        assert!(output.contains("0002    | Multiply\n"));
+    }
+
+    #[test]
+    fn test_output() {
+        let (mut output, mut diagnostics) = (Vec::new(), Vec::new());
+        let result = crate::evaluate(
+            "print 1 + 2;\nprint \"a\" + 1;",
+            &mut output,
+            &mut diagnostics,
+        );
+        assert!(result.is_err());
+        assert_eq!(output, b"3\n");
+        assert!(String::from_utf8(diagnostics)
+            .unwrap()
+            .starts_with("Runtime error: Operands must be two numbers"));
+    }
+
+    #[test]
+    fn test_stack_trace() {
+        let err = test_runner(
+            "fun a() { return -\"one\"; }\n\
+             fun b() {\n  a();\n}\n\
+             b();",
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Runtime error: Operand must be a number. \
+             at line 1, columns 17 - 18.\n\
+             [line 1] in a()\n\
+             [line 3] in b()\n\
+             [line 5] in script"
+        );
+        let BessyError::Runtime { trace, .. } =
+            test_runner("var a = 1;\na();").unwrap_err()
+        else {
+            panic!("expected a runtime error")
+        };
+        assert_eq!(
+            trace,
+            [TraceFrame {
+                function: None,
+                line_number: 2,
+            }]
+        );
+    }
+}
@@ -44,7 +44,32 @@ impl std::io::Write for WasmPrinter {
 
 #[wasm_bindgen]
 pub fn evaluate(input: String) -> String {
+    evaluate_with_options(input, false)
+}
+
+// `trace` prints the value stack and every instruction before it runs
+#[wasm_bindgen]
+pub fn evaluate_with_options(input: String, trace: bool) -> String {
     let mut output = WasmPrinter::default();
-    core::evaluate(input, &mut output);
-    output.0
+    let mut diagnostics = WasmPrinter::default();
+    let options = core::Options { trace };
+    let _ = core::evaluate_with_options(
+        &input,
+        &mut output,
+        &mut diagnostics,
+        options,
+    );
+    // the terminal shows the errors after whatever the program printed
+    // before it stopped
+    output.0 + &diagnostics.0
+}
+
+// lists the bytecode the input compiles to, or the errors that
+// stopped it from compiling
+#[wasm_bindgen]
+pub fn disassemble(input: String) -> String {
+    match core::disassemble(&input) {
+        Ok(listing) => listing,
+        Err(errors) => errors.render(&input),
+    }
 }